@@ -1,19 +1,30 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
 extern crate fastembed_axum;
 use fastembed_axum::embedding::embed_documents;
-use fastembed_axum::embedding::{EmbeddingRequestUnit, EmbeddingResponse};
+use fastembed_axum::embedding::truncation::Truncation;
+use fastembed_axum::embedding::{ChunkingStrategy, EmbeddingRequestUnit, EmbeddingResponse};
 
-fn main_embed_bench(docs: &Vec<String>) -> EmbeddingResponse {
-    let model = fastembed_axum::embedding::init_text_embedding();
+fn main_embed_bench(docs: &[String]) -> EmbeddingResponse {
+    let mut model = fastembed_axum::embedding::init_text_embedding().expect("Can't load model");
     let request_objects: Vec<EmbeddingRequestUnit> = docs
         .iter()
         .enumerate()
         .map(|(i, text)| EmbeddingRequestUnit {
             text_to_embed: text.to_string(),
-            id: i as i32,
+            id: (i as i64).into(),
+            metadata: None,
         })
         .collect();
-    embed_documents(&model, request_objects)
+    embed_documents(
+        &mut model,
+        request_objects,
+        &ChunkingStrategy::default(),
+        Truncation::default(),
+        None,
+    )
+    .expect("Embedding failed")
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
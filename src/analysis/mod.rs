@@ -0,0 +1,374 @@
+pub mod routes;
+
+use std::{num::NonZeroUsize, sync::Mutex};
+
+use lru::LruCache;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::collections::DistanceMetric;
+
+const DEFAULT_MAX_ITERATIONS: usize = 100;
+const LABEL_CACHE_CAPACITY: usize = 1024;
+
+fn default_max_iterations() -> usize {
+    DEFAULT_MAX_ITERATIONS
+}
+
+/// How documents are grouped into clusters.
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClusteringMethod {
+    /// Spherical k-means into exactly `k` clusters.
+    KMeans {
+        k: usize,
+        #[serde(default = "default_max_iterations")]
+        max_iterations: usize,
+    },
+    /// Agglomerative single-link clustering, joining documents with cosine similarity of at least `similarity`.
+    Threshold { similarity: f32 },
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    DistanceMetric::Cosine.score(a, b)
+}
+
+/// Mean of the vectors assigned to each of `k` clusters.
+pub fn centroids(vectors: &[Vec<f32>], assignments: &[usize], k: usize) -> Vec<Vec<f32>> {
+    let dimension = vectors.first().map_or(0, Vec::len);
+    let mut sums = vec![vec![0.0; dimension]; k];
+    let mut counts = vec![0usize; k];
+    for (vector, &cluster) in vectors.iter().zip(assignments) {
+        counts[cluster] += 1;
+        for (sum, value) in sums[cluster].iter_mut().zip(vector) {
+            *sum += value;
+        }
+    }
+    for (sum, count) in sums.iter_mut().zip(counts) {
+        if count > 0 {
+            sum.iter_mut().for_each(|value| *value /= count as f32);
+        }
+    }
+    sums
+}
+
+fn closest_centroid(vector: &[f32], centroids: &[Vec<f32>]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| cosine(vector, a).total_cmp(&cosine(vector, b)))
+        .map_or(0, |(i, _)| i)
+}
+
+/// Spherical k-means, seeded deterministically with farthest-point initialisation.
+/// Returns the cluster of every vector and the cluster centroids.
+pub fn kmeans(
+    vectors: &[Vec<f32>],
+    k: usize,
+    max_iterations: usize,
+) -> (Vec<usize>, Vec<Vec<f32>>) {
+    if vectors.is_empty() || k == 0 {
+        return (Vec::new(), Vec::new());
+    }
+    let mut centroids: Vec<Vec<f32>> = vec![vectors[0].clone()];
+    while centroids.len() < k.min(vectors.len()) {
+        let farthest = vectors
+            .iter()
+            .map(|vector| {
+                centroids
+                    .iter()
+                    .map(|centroid| cosine(vector, centroid))
+                    .fold(f32::MIN, f32::max)
+            })
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map_or(0, |(i, _)| i);
+        centroids.push(vectors[farthest].clone());
+    }
+
+    let mut assignments: Vec<usize> = vectors
+        .iter()
+        .map(|vector| closest_centroid(vector, &centroids))
+        .collect();
+    for _ in 0..max_iterations {
+        centroids = self::centroids(vectors, &assignments, centroids.len());
+        let next: Vec<usize> = vectors
+            .iter()
+            .map(|vector| closest_centroid(vector, &centroids))
+            .collect();
+        if next == assignments {
+            break;
+        }
+        assignments = next;
+    }
+    let centroids = self::centroids(vectors, &assignments, centroids.len());
+    (assignments, centroids)
+}
+
+/// Single-link clustering joining every pair with cosine similarity of at least `threshold`.
+/// Returns the cluster of every vector and the cluster centroids.
+pub fn threshold_clusters(vectors: &[Vec<f32>], threshold: f32) -> (Vec<usize>, Vec<Vec<f32>>) {
+    let mut parents: Vec<usize> = (0..vectors.len()).collect();
+    fn find(parents: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+        while parents[root] != root {
+            root = parents[root];
+        }
+        let mut node = i;
+        while parents[node] != root {
+            let next = parents[node];
+            parents[node] = root;
+            node = next;
+        }
+        root
+    }
+    for (i, j, _) in similar_pairs(vectors, threshold) {
+        let (a, b) = (find(&mut parents, i), find(&mut parents, j));
+        if a != b {
+            parents[b] = a;
+        }
+    }
+
+    // Number clusters in order of first appearance
+    let mut cluster_of_root = vec![usize::MAX; vectors.len()];
+    let mut k = 0;
+    let assignments: Vec<usize> = (0..vectors.len())
+        .map(|i| {
+            let root = find(&mut parents, i);
+            if cluster_of_root[root] == usize::MAX {
+                cluster_of_root[root] = k;
+                k += 1;
+            }
+            cluster_of_root[root]
+        })
+        .collect();
+    let centroids = centroids(vectors, &assignments, k);
+    (assignments, centroids)
+}
+
+/// Every pair of vectors with cosine similarity of at least `threshold`.
+pub fn similar_pairs(vectors: &[Vec<f32>], threshold: f32) -> Vec<(usize, usize, f32)> {
+    let mut pairs = Vec::new();
+    for (i, a) in vectors.iter().enumerate() {
+        for (j, b) in vectors.iter().enumerate().skip(i + 1) {
+            let similarity = cosine(a, b);
+            if similarity >= threshold {
+                pairs.push((i, j, similarity));
+            }
+        }
+    }
+    pairs
+}
+
+/// A class for zero-shot classification.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct Label {
+    pub name: String,
+    /// Text embedded in place of the name, e.g. "a complaint about billing".
+    pub description: Option<String>,
+    /// Example texts of this class, averaged into the label embedding.
+    #[serde(default)]
+    pub examples: Vec<String>,
+}
+
+impl Label {
+    /// Texts whose mean embedding represents the label.
+    pub fn texts(&self) -> Vec<String> {
+        let mut texts = vec![self
+            .description
+            .clone()
+            .unwrap_or_else(|| self.name.clone())];
+        texts.extend(self.examples.iter().cloned());
+        texts
+    }
+}
+
+/// Label embeddings per model, so repeated classification with the same labels is cheap.
+pub struct LabelEmbeddingCache {
+    entries: Mutex<LruCache<String, Vec<f32>>>,
+}
+
+impl LabelEmbeddingCache {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(
+                NonZeroUsize::new(LABEL_CACHE_CAPACITY).unwrap(),
+            )),
+        }
+    }
+
+    fn key(model: &str, label: &Label) -> String {
+        format!(
+            "{model}|{}",
+            serde_json::to_string(label).unwrap_or_default()
+        )
+    }
+
+    pub fn get(&self, model: &str, label: &Label) -> Option<Vec<f32>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get(&Self::key(model, label)).cloned()
+    }
+
+    pub fn insert(&self, model: &str, label: &Label, embedding: Vec<f32>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.put(Self::key(model, label), embedding);
+    }
+}
+
+impl Default for LabelEmbeddingCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Mean of a set of vectors.
+pub fn mean(vectors: &[Vec<f32>]) -> Vec<f32> {
+    centroids(vectors, &vec![0; vectors.len()], 1)
+        .pop()
+        .unwrap_or_default()
+}
+
+/// Cosine similarity of `vector` to every label embedding.
+pub fn label_scores(vector: &[f32], labels: &[Vec<f32>]) -> Vec<f32> {
+    labels.iter().map(|label| cosine(vector, label)).collect()
+}
+
+/// Indices of labels from the highest score down, ties keeping request order.
+pub fn rank_labels(scores: &[f32]) -> Vec<usize> {
+    let mut ranked: Vec<usize> = (0..scores.len()).collect();
+    ranked.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+    ranked
+}
+
+/// Indices of labels scoring at least `threshold`, highest first.
+pub fn labels_at_least(scores: &[f32], threshold: f32) -> Vec<usize> {
+    rank_labels(scores)
+        .into_iter()
+        .filter(|&i| scores[i] >= threshold)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unit vectors at 0°, ~37° and ~53°: neighbours have cosine 0.8 and 0.96,
+    /// the outer two 0.6, all exact in `f32`.
+    fn fan() -> Vec<Vec<f32>> {
+        vec![vec![1.0, 0.0], vec![4.0, 3.0], vec![3.0, 4.0]]
+    }
+
+    fn label(name: &str, description: Option<&str>) -> Label {
+        Label {
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            examples: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn labels_are_ranked_by_score() {
+        let labels = vec![vec![0.0, 1.0], vec![1.0, 0.0], vec![4.0, 3.0]];
+        let scores = label_scores(&[1.0, 0.0], &labels);
+        assert_eq!(scores, vec![0.0, 1.0, 0.8]);
+        assert_eq!(rank_labels(&scores), vec![1, 2, 0]);
+        // Ties go to the label listed first
+        assert_eq!(rank_labels(&[0.5, 0.9, 0.9]), vec![1, 2, 0]);
+        assert!(rank_labels(&[]).is_empty());
+    }
+
+    #[test]
+    fn multi_label_threshold_is_inclusive() {
+        let scores = [0.2, 0.8, 0.96];
+        assert_eq!(labels_at_least(&scores, 0.8), vec![2, 1]);
+        assert_eq!(labels_at_least(&scores, 0.81), vec![2]);
+        assert!(labels_at_least(&scores, 0.97).is_empty());
+        assert_eq!(labels_at_least(&scores, -1.0), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn label_texts_start_with_the_description() {
+        let mut billing = label("billing", Some("a complaint about billing"));
+        billing.examples = vec!["I was charged twice".to_string()];
+        assert_eq!(
+            billing.texts(),
+            vec!["a complaint about billing", "I was charged twice"]
+        );
+        assert_eq!(label("billing", None).texts(), vec!["billing"]);
+    }
+
+    #[test]
+    fn label_embeddings_are_cached_per_model_and_label() {
+        let cache = LabelEmbeddingCache::new();
+        let billing = label("billing", None);
+        assert_eq!(cache.get("model-a", &billing), None);
+        cache.insert("model-a", &billing, vec![1.0, 0.0]);
+        assert_eq!(cache.get("model-a", &billing), Some(vec![1.0, 0.0]));
+        assert_eq!(cache.get("model-a", &billing.clone()), Some(vec![1.0, 0.0]));
+        assert_eq!(cache.get("model-b", &billing), None);
+        // A new description changes the embedded text, so it isn't reused
+        assert_eq!(
+            cache.get("model-a", &label("billing", Some("an invoice question"))),
+            None
+        );
+    }
+
+    #[test]
+    fn label_embeddings_average_their_texts() {
+        assert_eq!(
+            mean(&[vec![1.0, 0.0], vec![0.0, 1.0], vec![2.0, 2.0]]),
+            vec![1.0, 1.0]
+        );
+        assert!(mean(&[]).is_empty());
+    }
+
+    #[test]
+    fn kmeans_is_deterministic() {
+        let vectors = vec![
+            vec![1.0, 0.0],
+            vec![0.9, 0.1],
+            vec![0.0, 1.0],
+            vec![0.1, 0.9],
+        ];
+        let (assignments, centroids) = kmeans(&vectors, 2, 10);
+        assert_eq!(assignments, vec![0, 0, 1, 1]);
+        assert_eq!(centroids, vec![vec![0.95, 0.05], vec![0.05, 0.95]]);
+        assert_eq!(kmeans(&vectors, 2, 10), (assignments, centroids));
+    }
+
+    #[test]
+    fn kmeans_with_more_clusters_than_vectors() {
+        // Every vector seeds its own cluster, the farthest from the first one next
+        let (assignments, centroids) = kmeans(&fan(), 5, 10);
+        assert_eq!(assignments, vec![0, 2, 1]);
+        assert_eq!(
+            centroids,
+            vec![vec![1.0, 0.0], vec![3.0, 4.0], vec![4.0, 3.0]]
+        );
+    }
+
+    #[test]
+    fn empty_input_has_no_clusters() {
+        assert_eq!(kmeans(&[], 3, 10), (vec![], vec![]));
+        assert_eq!(kmeans(&fan(), 0, 10), (vec![], vec![]));
+        assert_eq!(threshold_clusters(&[], 0.5), (vec![], vec![]));
+        assert!(similar_pairs(&[], 0.5).is_empty());
+    }
+
+    #[test]
+    fn similar_pairs_include_the_threshold() {
+        assert_eq!(similar_pairs(&fan(), 0.8), vec![(0, 1, 0.8), (1, 2, 0.96)]);
+        assert_eq!(similar_pairs(&fan(), 0.96), vec![(1, 2, 0.96)]);
+        assert!(similar_pairs(&fan(), 0.97).is_empty());
+    }
+
+    #[test]
+    fn threshold_clusters_link_through_neighbours() {
+        // The outer vectors are only 0.6 apart, but both reach the middle one
+        assert_eq!(threshold_clusters(&fan(), 0.8).0, vec![0, 0, 0]);
+        assert_eq!(threshold_clusters(&fan(), 0.96).0, vec![0, 1, 1]);
+        assert_eq!(threshold_clusters(&fan(), 0.97).0, vec![0, 1, 2]);
+        let (_, centroids) = threshold_clusters(&fan(), 0.96);
+        assert_eq!(centroids, vec![vec![1.0, 0.0], vec![3.5, 3.5]]);
+    }
+}
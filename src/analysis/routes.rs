@@ -0,0 +1,241 @@
+use aide::axum::{routing::post_with, ApiRouter};
+use axum::{extract::State, http::StatusCode};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    kmeans, label_scores, labels_at_least, mean, rank_labels, similar_pairs, threshold_clusters,
+    ClusteringMethod, Label,
+};
+use crate::{
+    embedding::{embed_texts, embed_with_state, DocumentId, EmbedSettings, EmbeddingRequestUnit},
+    server::{
+        errors::AppError,
+        extractors::{ClientId, Json},
+        limits::Fields,
+        state::AppState,
+    },
+};
+
+const DEFAULT_DUPLICATE_THRESHOLD: f32 = 0.95;
+
+pub fn analyze_routes(state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .api_route(
+            "/cluster",
+            post_with(cluster, |op| {
+                op.description("Cluster documents and find near-duplicate pairs.")
+            }),
+        )
+        .api_route(
+            "/classify",
+            post_with(classify, |op| {
+                op.description("Score documents against labels by embedding similarity.")
+            }),
+        )
+        .with_state(state)
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct ClusterRequest {
+    data: Vec<EmbeddingRequestUnit>,
+    method: ClusteringMethod,
+    /// Cosine similarity above which two documents are reported as near-duplicates, defaults to 0.95.
+    duplicate_threshold: Option<f32>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ClusterAssignment {
+    id: DocumentId,
+    cluster: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct NearDuplicate {
+    a: DocumentId,
+    b: DocumentId,
+    similarity: f32,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ClusterResponse {
+    number_of_clusters: usize,
+    assignments: Vec<ClusterAssignment>,
+    centroids: Vec<Vec<f32>>,
+    near_duplicates: Vec<NearDuplicate>,
+}
+
+pub async fn cluster(
+    State(state): State<AppState>,
+    client: ClientId,
+    Json(payload): Json<ClusterRequest>,
+) -> Result<(StatusCode, Json<ClusterResponse>), AppError> {
+    if let ClusteringMethod::KMeans { k, .. } = payload.method {
+        if k == 0 || k > payload.data.len() {
+            return Err(AppError::new(
+                "k must be between 1 and the number of documents",
+            ));
+        }
+    }
+    let embeddings = embed_with_state(
+        &state,
+        &client,
+        payload.data,
+        EmbedSettings::new(Fields::DATA),
+    )
+    .await?;
+    let (ids, vectors): (Vec<DocumentId>, Vec<Vec<f32>>) = embeddings
+        .into_embeddings()
+        .into_iter()
+        .map(|object| {
+            let (id, embeddings, _) = object.into_parts();
+            (id, embeddings.into_iter().next().unwrap_or_default())
+        })
+        .unzip();
+
+    let (assignments, centroids) = match payload.method {
+        ClusteringMethod::KMeans { k, max_iterations } => kmeans(&vectors, k, max_iterations),
+        ClusteringMethod::Threshold { similarity } => threshold_clusters(&vectors, similarity),
+    };
+    let near_duplicates = similar_pairs(
+        &vectors,
+        payload
+            .duplicate_threshold
+            .unwrap_or(DEFAULT_DUPLICATE_THRESHOLD),
+    )
+    .into_iter()
+    .map(|(i, j, similarity)| NearDuplicate {
+        a: ids[i].clone(),
+        b: ids[j].clone(),
+        similarity,
+    })
+    .collect();
+    Ok((
+        StatusCode::OK,
+        Json(ClusterResponse {
+            number_of_clusters: centroids.len(),
+            assignments: ids
+                .iter()
+                .zip(assignments)
+                .map(|(id, cluster)| ClusterAssignment {
+                    id: id.clone(),
+                    cluster,
+                })
+                .collect(),
+            centroids,
+            near_duplicates,
+        }),
+    ))
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct ClassifyRequest {
+    data: Vec<EmbeddingRequestUnit>,
+    labels: Vec<Label>,
+    /// Also return every label scoring at least this, for multi-label classification.
+    threshold: Option<f32>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct LabelScore {
+    label: String,
+    /// Cosine similarity between the document and the label.
+    score: f32,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Classification {
+    id: DocumentId,
+    /// The highest scoring label.
+    label: String,
+    /// Scores of every label, in request order.
+    scores: Vec<LabelScore>,
+    /// Labels scoring at least `threshold`, highest first, when a threshold is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    labels: Option<Vec<String>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ClassifyResponse {
+    classifications: Vec<Classification>,
+}
+
+/// Embeddings of every label, reusing cached ones and embedding the rest in one batch.
+async fn label_embeddings(
+    state: &AppState,
+    client: &ClientId,
+    labels: &[Label],
+) -> Result<Vec<Vec<f32>>, AppError> {
+    let model = &state.model_info.name;
+    let mut embeddings: Vec<Option<Vec<f32>>> = labels
+        .iter()
+        .map(|label| state.label_cache.get(model, label))
+        .collect();
+    let missing: Vec<usize> = (0..labels.len())
+        .filter(|&i| embeddings[i].is_none())
+        .collect();
+    if !missing.is_empty() {
+        let texts: Vec<Vec<String>> = missing.iter().map(|&i| labels[i].texts()).collect();
+        let texts_to_embed = texts.iter().flatten().cloned().collect();
+        let mut vectors = embed_texts(state, client, texts_to_embed, Fields::Single("labels"))
+            .await?
+            .into_iter();
+        for (&i, label_texts) in missing.iter().zip(&texts) {
+            let label_vectors: Vec<Vec<f32>> = vectors.by_ref().take(label_texts.len()).collect();
+            let embedding = mean(&label_vectors);
+            state
+                .label_cache
+                .insert(model, &labels[i], embedding.clone());
+            embeddings[i] = Some(embedding);
+        }
+    }
+    Ok(embeddings.into_iter().flatten().collect())
+}
+
+pub async fn classify(
+    State(state): State<AppState>,
+    client: ClientId,
+    Json(payload): Json<ClassifyRequest>,
+) -> Result<(StatusCode, Json<ClassifyResponse>), AppError> {
+    if payload.labels.is_empty() {
+        return Err(AppError::new("at least one label is required"));
+    }
+    let labels = label_embeddings(&state, &client, &payload.labels).await?;
+    let embeddings = embed_with_state(
+        &state,
+        &client,
+        payload.data,
+        EmbedSettings::new(Fields::DATA),
+    )
+    .await?;
+    let classifications = embeddings
+        .into_embeddings()
+        .into_iter()
+        .map(|object| {
+            let (id, embeddings, _) = object.into_parts();
+            let vector = embeddings.into_iter().next().unwrap_or_default();
+            let scores = label_scores(&vector, &labels);
+            let best = rank_labels(&scores).first().copied().unwrap_or(0);
+            Classification {
+                id,
+                label: payload.labels[best].name.clone(),
+                labels: payload.threshold.map(|threshold| {
+                    labels_at_least(&scores, threshold)
+                        .into_iter()
+                        .map(|i| payload.labels[i].name.clone())
+                        .collect()
+                }),
+                scores: payload
+                    .labels
+                    .iter()
+                    .zip(scores)
+                    .map(|(label, score)| LabelScore {
+                        label: label.name.clone(),
+                        score,
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    Ok((StatusCode::OK, Json(ClassifyResponse { classifications })))
+}
@@ -0,0 +1,361 @@
+//! Typed clients for the HTTP API, sharing the request and response types the
+//! server publishes in its OpenAPI document.
+
+use std::time::Duration;
+
+use reqwest::{header::RETRY_AFTER, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+use uuid::Uuid;
+
+use crate::{
+    embedding::{
+        preprocess::Preprocessing, truncation::Truncation, ChunkingStrategy, EmbeddingRequest,
+        EmbeddingRequestUnit, EmbeddingResponse, JSONModelInfo,
+    },
+    server::extractors::API_KEY_HEADER,
+};
+
+const DEFAULT_BASE_URL: &str = "http://127.0.0.1:3100";
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_INITIAL_BACKOFF_MS: u64 = 200;
+const DEFAULT_MAX_BACKOFF_MS: u64 = 10_000;
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+/// The error body returned by the server, read from either the default JSON error
+/// or an `application/problem+json` document.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApiError {
+    /// The message, `detail` in a problem document.
+    #[serde(alias = "detail")]
+    pub error: String,
+    pub error_id: Option<Uuid>,
+    /// Stable, machine-readable error code such as `input_too_long`.
+    pub code: Option<String>,
+    pub error_details: Option<serde_json::Value>,
+    /// Only sent in problem documents.
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The request could not be sent or the response could not be read.
+    Http(reqwest::Error),
+    /// The server answered with an error status.
+    Api {
+        status: StatusCode,
+        error: Box<ApiError>,
+    },
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Http(error) => write!(f, "Request failed: {error}"),
+            Self::Api { status, error } => write!(f, "Server returned {status}: {}", error.error),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(error: reqwest::Error) -> Self {
+        Self::Http(error)
+    }
+}
+
+/// Connection settings shared by [`Client`] and [`BlockingClient`].
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// Server address including any API base path, e.g. `http://127.0.0.1:3100`.
+    pub base_url: String,
+    /// Sent as the `X-Auth-Key` header.
+    pub api_key: Option<String>,
+    /// Retries after the first attempt for connection errors, `429` and `5xx` responses.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled after every attempt.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            api_key: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff: Duration::from_millis(DEFAULT_INITIAL_BACKOFF_MS),
+            max_backoff: Duration::from_millis(DEFAULT_MAX_BACKOFF_MS),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Delay before retry number `attempt`, preferring the server's `Retry-After`.
+    fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        retry_after.unwrap_or_else(|| {
+            self.initial_backoff
+                .saturating_mul(2u32.saturating_pow(attempt))
+                .min(self.max_backoff)
+        })
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout()
+}
+
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+fn api_error(status: StatusCode, body: &[u8]) -> ClientError {
+    let error = serde_json::from_slice(body).unwrap_or_else(|_| ApiError {
+        error: String::from_utf8_lossy(body).into_owned(),
+        error_id: None,
+        code: None,
+        error_details: None,
+        request_id: None,
+    });
+    ClientError::Api {
+        status,
+        error: Box::new(error),
+    }
+}
+
+/// Options of `POST /embed/generate`, every one defaulting to the server's choice.
+#[derive(Clone, Debug, Default)]
+pub struct EmbedOptions {
+    pub preprocessing: Option<Preprocessing>,
+    pub chunking: Option<ChunkingStrategy>,
+    pub truncation: Option<Truncation>,
+}
+
+impl EmbedOptions {
+    pub fn with_preprocessing(mut self, preprocessing: Preprocessing) -> Self {
+        self.preprocessing = Some(preprocessing);
+        self
+    }
+
+    pub fn with_chunking(mut self, chunking: ChunkingStrategy) -> Self {
+        self.chunking = Some(chunking);
+        self
+    }
+
+    pub fn with_truncation(mut self, truncation: Truncation) -> Self {
+        self.truncation = Some(truncation);
+        self
+    }
+
+    fn request(&self, data: Vec<EmbeddingRequestUnit>) -> EmbeddingRequest {
+        EmbeddingRequest {
+            data,
+            preprocessing: self.preprocessing.clone(),
+            chunking: self.chunking.clone(),
+            truncation: self.truncation,
+        }
+    }
+}
+
+/// Async client for the HTTP API.
+#[derive(Clone)]
+pub struct Client {
+    config: ClientConfig,
+    http: reqwest::Client,
+}
+
+impl Client {
+    pub fn new(config: ClientConfig) -> Result<Self, ClientError> {
+        let http = reqwest::Client::builder().timeout(config.timeout).build()?;
+        Ok(Self { config, http })
+    }
+
+    /// Embed documents with `POST /embed/generate` and the server's default options.
+    pub async fn embed(
+        &self,
+        data: Vec<EmbeddingRequestUnit>,
+    ) -> Result<EmbeddingResponse, ClientError> {
+        self.embed_with_options(data, &EmbedOptions::default())
+            .await
+    }
+
+    /// Embed documents with `POST /embed/generate`.
+    pub async fn embed_with_options(
+        &self,
+        data: Vec<EmbeddingRequestUnit>,
+        options: &EmbedOptions,
+    ) -> Result<EmbeddingResponse, ClientError> {
+        let request = options.request(data);
+        self.send(|| {
+            self.http
+                .post(self.config.url("/embed/generate"))
+                .json(&request)
+        })
+        .await
+    }
+
+    pub async fn model_info(&self) -> Result<JSONModelInfo, ClientError> {
+        self.send(|| self.http.get(self.config.url("/embed/model-info")))
+            .await
+    }
+
+    pub async fn available_models(&self) -> Result<Vec<JSONModelInfo>, ClientError> {
+        self.send(|| self.http.get(self.config.url("/embed/available-models")))
+            .await
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<T, ClientError> {
+        let mut attempt = 0;
+        loop {
+            let mut request = build();
+            if let Some(api_key) = &self.config.api_key {
+                request = request.header(API_KEY_HEADER, api_key);
+            }
+            let (error, retry_after) = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    return Ok(response.json().await?);
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let retry_after = retry_after(response.headers());
+                    let error = api_error(status, &response.bytes().await?);
+                    if !is_retryable(status) {
+                        return Err(error);
+                    }
+                    (error, retry_after)
+                }
+                Err(error) if is_retryable_error(&error) => (error.into(), None),
+                Err(error) => return Err(error.into()),
+            };
+            if attempt >= self.config.max_retries {
+                return Err(error);
+            }
+            tokio::time::sleep(self.config.backoff(attempt, retry_after)).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Blocking client for the HTTP API. Must not be used from within an async runtime.
+#[derive(Clone)]
+pub struct BlockingClient {
+    config: ClientConfig,
+    http: reqwest::blocking::Client,
+}
+
+impl BlockingClient {
+    pub fn new(config: ClientConfig) -> Result<Self, ClientError> {
+        let http = reqwest::blocking::Client::builder()
+            .timeout(config.timeout)
+            .build()?;
+        Ok(Self { config, http })
+    }
+
+    /// Embed documents with `POST /embed/generate` and the server's default options.
+    pub fn embed(&self, data: Vec<EmbeddingRequestUnit>) -> Result<EmbeddingResponse, ClientError> {
+        self.embed_with_options(data, &EmbedOptions::default())
+    }
+
+    /// Embed documents with `POST /embed/generate`.
+    pub fn embed_with_options(
+        &self,
+        data: Vec<EmbeddingRequestUnit>,
+        options: &EmbedOptions,
+    ) -> Result<EmbeddingResponse, ClientError> {
+        let request = options.request(data);
+        self.send(|| {
+            self.http
+                .post(self.config.url("/embed/generate"))
+                .json(&request)
+        })
+    }
+
+    pub fn model_info(&self) -> Result<JSONModelInfo, ClientError> {
+        self.send(|| self.http.get(self.config.url("/embed/model-info")))
+    }
+
+    pub fn available_models(&self) -> Result<Vec<JSONModelInfo>, ClientError> {
+        self.send(|| self.http.get(self.config.url("/embed/available-models")))
+    }
+
+    fn send<T: DeserializeOwned>(
+        &self,
+        build: impl Fn() -> reqwest::blocking::RequestBuilder,
+    ) -> Result<T, ClientError> {
+        let mut attempt = 0;
+        loop {
+            let mut request = build();
+            if let Some(api_key) = &self.config.api_key {
+                request = request.header(API_KEY_HEADER, api_key);
+            }
+            let (error, retry_after) = match request.send() {
+                Ok(response) if response.status().is_success() => {
+                    return Ok(response.json()?);
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let retry_after = retry_after(response.headers());
+                    let error = api_error(status, &response.bytes()?);
+                    if !is_retryable(status) {
+                        return Err(error);
+                    }
+                    (error, retry_after)
+                }
+                Err(error) if is_retryable_error(&error) => (error.into(), None),
+                Err(error) => return Err(error.into()),
+            };
+            if attempt >= self.config.max_retries {
+                return Err(error);
+            }
+            std::thread::sleep(self.config.backoff(attempt, retry_after));
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,518 @@
+pub mod routes;
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+
+use hnsw_rs::prelude::{DistCosine, DistL2, Hnsw, Neighbour};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    embedding::{DocumentId, Metadata},
+    server::config::env_or,
+};
+
+const DEFAULT_COLLECTIONS_DIR: &str = "./.fastembed_collections";
+const MAX_NB_CONNECTION: usize = 16;
+const MAX_LAYER: usize = 16;
+const MAX_ELEMENTS: usize = 100_000;
+const EF_CONSTRUCTION: usize = 200;
+const MIN_EF_SEARCH: usize = 64;
+/// The index is rebuilt once replaced documents leave more stale points than this
+/// fraction of live ones.
+const MAX_STALE_RATIO: usize = 2;
+const MIN_STALE_POINTS_TO_REBUILD: usize = 64;
+
+/// How search results are scored.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceMetric {
+    /// Cosine similarity, higher is closer.
+    #[default]
+    Cosine,
+    /// Dot product, higher is closer.
+    Dot,
+    /// Euclidean distance, lower is closer.
+    #[serde(alias = "euclidean")]
+    L2,
+}
+
+impl DistanceMetric {
+    /// Score two vectors with this metric.
+    pub fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Self::Cosine => {
+                let norm = norm(a) * norm(b);
+                if norm == 0.0 {
+                    0.0
+                } else {
+                    dot(a, b) / norm
+                }
+            }
+            Self::Dot => dot(a, b),
+            Self::L2 => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f32>()
+                .sqrt(),
+        }
+    }
+
+    /// Whether a higher score means the vectors are closer.
+    pub fn higher_is_closer(&self) -> bool {
+        !matches!(self, Self::L2)
+    }
+
+    /// Order scores closest first.
+    pub fn compare(&self, a: f32, b: f32) -> std::cmp::Ordering {
+        if self.higher_is_closer() {
+            b.total_cmp(&a)
+        } else {
+            a.total_cmp(&b)
+        }
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn norm(a: &[f32]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+/// Candidate search for a collection. hnsw_rs panics on negative distances, which
+/// its `DistDot` returns for vectors that aren't unit length, so dot product
+/// collections search by cosine and candidates are then ordered by dot product.
+/// Both rank identically for the normalized embeddings models produce.
+enum Index {
+    Cosine(Hnsw<'static, f32, DistCosine>),
+    L2(Hnsw<'static, f32, DistL2>),
+}
+
+impl Index {
+    fn new(metric: DistanceMetric) -> Self {
+        match metric {
+            DistanceMetric::Cosine | DistanceMetric::Dot => Self::Cosine(Hnsw::new(
+                MAX_NB_CONNECTION,
+                MAX_ELEMENTS,
+                MAX_LAYER,
+                EF_CONSTRUCTION,
+                DistCosine {},
+            )),
+            DistanceMetric::L2 => Self::L2(Hnsw::new(
+                MAX_NB_CONNECTION,
+                MAX_ELEMENTS,
+                MAX_LAYER,
+                EF_CONSTRUCTION,
+                DistL2 {},
+            )),
+        }
+    }
+
+    fn insert(&self, embedding: &[f32], point: usize) {
+        match self {
+            Self::Cosine(index) => index.insert((embedding, point)),
+            Self::L2(index) => index.insert((embedding, point)),
+        }
+    }
+
+    fn search(&self, query: &[f32], k: usize, ef: usize) -> Vec<Neighbour> {
+        match self {
+            Self::Cosine(index) => index.search(query, k, ef),
+            Self::L2(index) => index.search(query, k, ef),
+        }
+    }
+}
+
+/// A document stored in a collection.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoredDocument {
+    pub id: DocumentId,
+    pub text: String,
+    pub embedding: Vec<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+}
+
+/// What is written to disk for a collection, the index is rebuilt on load.
+#[derive(Serialize, Deserialize)]
+struct PersistedCollection {
+    model: String,
+    metric: DistanceMetric,
+    documents: Vec<StoredDocument>,
+}
+
+/// A named set of documents searchable by embedding similarity.
+pub struct Collection {
+    pub model: String,
+    pub metric: DistanceMetric,
+    documents: HashMap<DocumentId, StoredDocument>,
+    /// Document id of every point inserted into the index, in insertion order.
+    points: Vec<DocumentId>,
+    /// Latest point of every document, older points of re-upserted documents are skipped.
+    latest_point: HashMap<DocumentId, usize>,
+    index: Index,
+}
+
+impl Collection {
+    pub fn new(model: &str, metric: DistanceMetric) -> Self {
+        Self {
+            model: model.to_string(),
+            metric,
+            documents: HashMap::new(),
+            points: Vec::new(),
+            latest_point: HashMap::new(),
+            index: Index::new(metric),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Insert or replace a document.
+    pub fn upsert(&mut self, document: StoredDocument) {
+        self.insert_point(&document);
+        self.documents.insert(document.id.clone(), document);
+        let stale = self.points.len() - self.latest_point.len();
+        if stale >= MIN_STALE_POINTS_TO_REBUILD && stale > self.latest_point.len() * MAX_STALE_RATIO
+        {
+            self.rebuild_index();
+        }
+    }
+
+    fn insert_point(&mut self, document: &StoredDocument) {
+        let point = self.points.len();
+        self.index.insert(&document.embedding, point);
+        self.points.push(document.id.clone());
+        self.latest_point.insert(document.id.clone(), point);
+    }
+
+    /// Replace the index with one holding only the latest point of every document.
+    fn rebuild_index(&mut self) {
+        self.index = Index::new(self.metric);
+        self.points.clear();
+        self.latest_point.clear();
+        let documents = std::mem::take(&mut self.documents);
+        for document in documents.values() {
+            self.insert_point(document);
+        }
+        self.documents = documents;
+    }
+
+    /// The `top_k` closest documents to `query`, closest first.
+    pub fn search(&self, query: &[f32], top_k: usize) -> Vec<(&StoredDocument, f32)> {
+        // Replaced documents leave stale points in the index, so ask for enough extra neighbours
+        let stale = self.points.len() - self.latest_point.len();
+        // hnsw_rs allocates for `k` results up front, so never ask for more than exist
+        let k = top_k.saturating_add(stale).min(self.points.len());
+        let mut results: Vec<(&StoredDocument, f32)> = self
+            .index
+            .search(query, k, k.max(MIN_EF_SEARCH))
+            .into_iter()
+            .filter_map(|neighbour| {
+                let id = self.points.get(neighbour.d_id)?;
+                if self.latest_point.get(id) == Some(&neighbour.d_id) {
+                    self.documents.get(id)
+                } else {
+                    None
+                }
+            })
+            .map(|document| (document, self.metric.score(query, &document.embedding)))
+            .collect();
+        results.sort_by(|(_, a), (_, b)| self.metric.compare(*a, *b));
+        results.truncate(top_k);
+        results
+    }
+
+    fn to_persisted(&self) -> PersistedCollection {
+        PersistedCollection {
+            model: self.model.clone(),
+            metric: self.metric,
+            documents: self.documents.values().cloned().collect(),
+        }
+    }
+
+    fn from_persisted(persisted: PersistedCollection) -> Self {
+        let mut collection = Self::new(&persisted.model, persisted.metric);
+        for document in persisted.documents {
+            collection.upsert(document);
+        }
+        collection
+    }
+}
+
+#[derive(Debug)]
+pub enum CollectionError {
+    InvalidName,
+    NotFound,
+    ModelMismatch { collection_model: String },
+    Io(std::io::Error),
+    Serde(serde_json::Error),
+}
+
+impl std::fmt::Display for CollectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidName => write!(
+                f,
+                "Collection names may only contain letters, digits, '-' and '_'"
+            ),
+            Self::NotFound => write!(f, "The collection you have searched for has not been found"),
+            Self::ModelMismatch { collection_model } => write!(
+                f,
+                "The collection was built with model {collection_model}, which is not the current model"
+            ),
+            Self::Io(error) => write!(f, "Failed to persist collection: {error}"),
+            Self::Serde(error) => write!(f, "Failed to serialize collection: {error}"),
+        }
+    }
+}
+
+/// Every collection, persisted as one JSON file each under `dir` by `flush`.
+pub struct Collections {
+    dir: PathBuf,
+    collections: RwLock<HashMap<String, Collection>>,
+    /// Collections changed since they were last written.
+    dirty: Mutex<HashSet<String>>,
+}
+
+impl Collections {
+    /// Load every collection found in `dir`.
+    pub fn load(dir: PathBuf) -> Self {
+        let mut collections = HashMap::new();
+        let entries: Vec<std::io::Result<std::fs::DirEntry>> = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries.collect(),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => {
+                tracing::warn!(%error, dir = %dir.display(), "failed to list collections");
+                Vec::new()
+            }
+        };
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(error) => {
+                    tracing::warn!(%error, dir = %dir.display(), "failed to read collection entry");
+                    continue;
+                }
+            };
+            let Some(name) = collection_name(&path) else {
+                continue;
+            };
+            match std::fs::read(&path)
+                .map_err(CollectionError::Io)
+                .and_then(|bytes| serde_json::from_slice(&bytes).map_err(CollectionError::Serde))
+            {
+                Ok(persisted) => {
+                    collections.insert(name, Collection::from_persisted(persisted));
+                }
+                Err(error) => {
+                    tracing::warn!(%error, path = %path.display(), "failed to load collection")
+                }
+            }
+        }
+        Self {
+            dir,
+            collections: RwLock::new(collections),
+            dirty: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::load(env_or(
+            "FASTEMBED_COLLECTIONS_DIR",
+            PathBuf::from(DEFAULT_COLLECTIONS_DIR),
+        ))
+    }
+
+    /// Insert documents into a collection, creating it with `metric` if it doesn't exist.
+    /// Returns the number of documents in the collection afterwards.
+    pub fn upsert(
+        &self,
+        name: &str,
+        model: &str,
+        metric: Option<DistanceMetric>,
+        documents: Vec<StoredDocument>,
+    ) -> Result<usize, CollectionError> {
+        validate_name(name)?;
+        let mut collections = self.collections.write().unwrap_or_else(|e| e.into_inner());
+        let collection = collections
+            .entry(name.to_string())
+            .or_insert_with(|| Collection::new(model, metric.unwrap_or_default()));
+        if collection.model != model {
+            return Err(CollectionError::ModelMismatch {
+                collection_model: collection.model.clone(),
+            });
+        }
+        for document in documents {
+            collection.upsert(document);
+        }
+        self.dirty
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.to_string());
+        Ok(collection.len())
+    }
+
+    /// Run `f` against a collection built with `model`.
+    pub fn with_collection<T>(
+        &self,
+        name: &str,
+        model: &str,
+        f: impl FnOnce(&Collection) -> T,
+    ) -> Result<T, CollectionError> {
+        let collections = self.collections.read().unwrap_or_else(|e| e.into_inner());
+        let collection = collections.get(name).ok_or(CollectionError::NotFound)?;
+        if collection.model != model {
+            return Err(CollectionError::ModelMismatch {
+                collection_model: collection.model.clone(),
+            });
+        }
+        Ok(f(collection))
+    }
+
+    /// Write every collection changed since the last flush, retrying failed ones next time.
+    pub fn flush(&self) {
+        let dirty = std::mem::take(&mut *self.dirty.lock().unwrap_or_else(|e| e.into_inner()));
+        for name in dirty {
+            // Serialize under the read lock, then write without holding it
+            let bytes = {
+                let collections = self.collections.read().unwrap_or_else(|e| e.into_inner());
+                let Some(collection) = collections.get(&name) else {
+                    continue;
+                };
+                serde_json::to_vec(&collection.to_persisted()).map_err(CollectionError::Serde)
+            };
+            if let Err(error) = bytes.and_then(|bytes| self.save(&name, bytes)) {
+                tracing::warn!(%error, collection = %name, "failed to save collection");
+                self.dirty
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(name);
+            }
+        }
+    }
+
+    fn save(&self, name: &str, bytes: Vec<u8>) -> Result<(), CollectionError> {
+        std::fs::create_dir_all(&self.dir).map_err(CollectionError::Io)?;
+        let path = self.dir.join(format!("{name}.json"));
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, bytes).map_err(CollectionError::Io)?;
+        std::fs::rename(tmp, path).map_err(CollectionError::Io)
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), CollectionError> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(CollectionError::InvalidName)
+    }
+}
+
+fn collection_name(path: &Path) -> Option<String> {
+    if path.extension()? != "json" {
+        return None;
+    }
+    let name = path.file_stem()?.to_str()?;
+    validate_name(name).ok()?;
+    Some(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: i64, embedding: Vec<f32>) -> StoredDocument {
+        StoredDocument {
+            id: DocumentId::Int(id),
+            text: format!("document {id}"),
+            embedding,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn dot_collections_accept_the_same_vector_twice() {
+        let mut collection = Collection::new("model", DistanceMetric::Dot);
+        collection.upsert(document(1, vec![3.0, 4.0]));
+        collection.upsert(document(1, vec![3.0, 4.0]));
+        collection.upsert(document(2, vec![0.6, 0.8]));
+        let results = collection.search(&[3.0, 4.0], 10);
+        let scores: Vec<(DocumentId, f32)> = results
+            .into_iter()
+            .map(|(document, score)| (document.id.clone(), score))
+            .collect();
+        assert_eq!(
+            scores,
+            vec![(DocumentId::Int(1), 25.0), (DocumentId::Int(2), 5.0)]
+        );
+    }
+
+    #[test]
+    fn search_returns_the_closest_documents_first() {
+        for metric in [DistanceMetric::Cosine, DistanceMetric::L2] {
+            let mut collection = Collection::new("model", metric);
+            collection.upsert(document(1, vec![1.0, 0.0]));
+            collection.upsert(document(2, vec![0.0, 1.0]));
+            collection.upsert(document(3, vec![0.7, 0.7]));
+            let ids: Vec<DocumentId> = collection
+                .search(&[1.0, 0.1], 2)
+                .into_iter()
+                .map(|(document, _)| document.id.clone())
+                .collect();
+            assert_eq!(ids, vec![DocumentId::Int(1), DocumentId::Int(3)]);
+        }
+    }
+
+    #[test]
+    fn huge_top_k_does_not_overflow() {
+        let mut collection = Collection::new("model", DistanceMetric::Cosine);
+        collection.upsert(document(1, vec![1.0, 0.0]));
+        collection.upsert(document(1, vec![0.0, 1.0]));
+        assert_eq!(collection.search(&[1.0, 0.0], usize::MAX).len(), 1);
+    }
+
+    #[test]
+    fn replacing_documents_rebuilds_the_index() {
+        let mut collection = Collection::new("model", DistanceMetric::Cosine);
+        for i in 0..1000 {
+            collection.upsert(document(1, vec![1.0, i as f32]));
+        }
+        assert_eq!(collection.len(), 1);
+        assert!(collection.points.len() <= MIN_STALE_POINTS_TO_REBUILD + 1);
+        let results = collection.search(&[1.0, 999.0], 1);
+        assert_eq!(results[0].0.embedding, vec![1.0, 999.0]);
+    }
+
+    #[test]
+    fn changed_collections_are_written_on_flush() {
+        let dir = std::env::temp_dir().join(format!("collections-{}", uuid::Uuid::new_v4()));
+        let collections = Collections::load(dir.clone());
+        collections
+            .upsert("docs", "model", None, vec![document(1, vec![1.0, 0.0])])
+            .unwrap();
+        assert!(!dir.join("docs.json").exists());
+        collections.flush();
+        let reloaded = Collections::load(dir.clone());
+        let len = reloaded
+            .with_collection("docs", "model", Collection::len)
+            .unwrap();
+        assert_eq!(len, 1);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
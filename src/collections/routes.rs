@@ -0,0 +1,195 @@
+use aide::axum::{routing::post_with, ApiRouter};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{CollectionError, DistanceMetric, StoredDocument};
+use crate::{
+    embedding::{
+        embed_texts, embed_with_state, DocumentId, EmbedSettings, EmbeddingRequestUnit, Metadata,
+    },
+    server::{
+        errors::AppError,
+        extractors::{ClientId, Json},
+        limits::Fields,
+        state::AppState,
+    },
+};
+
+const DEFAULT_TOP_K: usize = 10;
+
+pub fn collection_routes(state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .api_route(
+            "/{name}/upsert",
+            post_with(upsert, |op| {
+                op.description(
+                    "Embed documents and store them in a collection, creating it if needed.",
+                )
+            }),
+        )
+        .api_route(
+            "/{name}/search",
+            post_with(search, |op| {
+                op.description("Embed a query and return the closest documents in a collection.")
+            }),
+        )
+        .with_state(state)
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct CollectionPath {
+    /// Collection name, letters, digits, '-' and '_' only.
+    name: String,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct UpsertRequest {
+    data: Vec<EmbeddingRequestUnit>,
+    /// Metric used when the collection is created, ignored afterwards.
+    metric: Option<DistanceMetric>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct UpsertResponse {
+    collection: String,
+    upserted: usize,
+    total_documents: usize,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct SearchRequest {
+    query: String,
+    /// Number of results, defaults to 10 and at most `FASTEMBED_MAX_TOP_K`.
+    top_k: Option<usize>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SearchHit {
+    id: DocumentId,
+    /// Similarity for cosine and dot, distance for l2.
+    score: f32,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<Metadata>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SearchResponse {
+    collection: String,
+    metric: DistanceMetric,
+    results: Vec<SearchHit>,
+}
+
+impl From<CollectionError> for AppError {
+    fn from(error: CollectionError) -> Self {
+        let status = match error {
+            CollectionError::InvalidName => StatusCode::BAD_REQUEST,
+            CollectionError::NotFound => StatusCode::NOT_FOUND,
+            CollectionError::ModelMismatch { .. } => StatusCode::CONFLICT,
+            CollectionError::Io(_) | CollectionError::Serde(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        AppError::new(&error.to_string()).with_status(status)
+    }
+}
+
+pub async fn upsert(
+    State(state): State<AppState>,
+    client: ClientId,
+    Path(path): Path<CollectionPath>,
+    Json(payload): Json<UpsertRequest>,
+) -> Result<(StatusCode, Json<UpsertResponse>), AppError> {
+    let texts: Vec<String> = payload
+        .data
+        .iter()
+        .map(|unit| unit.text_to_embed.clone())
+        .collect();
+    let embeddings = embed_with_state(
+        &state,
+        &client,
+        payload.data,
+        EmbedSettings::new(Fields::DATA),
+    )
+    .await?;
+    // Documents that produced no chunk, e.g. empty text, can't be searched for
+    let documents: Vec<StoredDocument> = embeddings
+        .into_embeddings()
+        .into_iter()
+        .zip(texts)
+        .enumerate()
+        .map(|(i, (object, text))| {
+            let (id, embeddings, metadata) = object.into_parts();
+            let embedding = embeddings.into_iter().next().ok_or_else(|| {
+                AppError::new(&format!("data[{i}] produced no embedding to store"))
+                    .with_status(StatusCode::UNPROCESSABLE_ENTITY)
+            })?;
+            Ok(StoredDocument {
+                id,
+                text,
+                embedding,
+                metadata,
+            })
+        })
+        .collect::<Result<_, AppError>>()?;
+    let upserted = documents.len();
+    let total_documents = state.collections.upsert(
+        &path.name,
+        &state.model_info.name,
+        payload.metric,
+        documents,
+    )?;
+    Ok((
+        StatusCode::OK,
+        Json(UpsertResponse {
+            collection: path.name,
+            upserted,
+            total_documents,
+        }),
+    ))
+}
+
+pub async fn search(
+    State(state): State<AppState>,
+    client: ClientId,
+    Path(path): Path<CollectionPath>,
+    Json(payload): Json<SearchRequest>,
+) -> Result<(StatusCode, Json<SearchResponse>), AppError> {
+    let top_k = payload.top_k.unwrap_or(DEFAULT_TOP_K);
+    state.limits.check_top_k(top_k)?;
+    let query_embedding = embed_texts(
+        &state,
+        &client,
+        vec![payload.query],
+        Fields::Single("query"),
+    )
+    .await?
+    .pop()
+    .unwrap_or_default();
+    let (metric, results) =
+        state
+            .collections
+            .with_collection(&path.name, &state.model_info.name, |collection| {
+                let results = collection
+                    .search(&query_embedding, top_k)
+                    .into_iter()
+                    .map(|(document, score)| SearchHit {
+                        id: document.id.clone(),
+                        score,
+                        text: document.text.clone(),
+                        metadata: document.metadata.clone(),
+                    })
+                    .collect();
+                (collection.metric, results)
+            })?;
+    Ok((
+        StatusCode::OK,
+        Json(SearchResponse {
+            collection: path.name,
+            metric,
+            results,
+        }),
+    ))
+}
@@ -0,0 +1,450 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use aide::axum::{routing::delete_with, ApiRouter};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use lru::LruCache;
+use redb::{Database, TableDefinition};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::server::{
+    config::env_opt,
+    config::env_or,
+    errors::AppError,
+    extractors::{ClientId, Json},
+    state::AppState,
+};
+
+const DEFAULT_CACHE_CAPACITY: usize = 10_000;
+const EMBEDDINGS_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("embeddings");
+
+#[derive(Clone, Debug)]
+pub struct EmbeddingCacheConfig {
+    /// Number of embeddings kept in memory, 0 disables the cache.
+    pub capacity: usize,
+    /// Optional database file persisting embeddings across restarts.
+    pub disk_path: Option<PathBuf>,
+    /// API key allowed to purge the cache, purging is disabled without one.
+    pub admin_key: Option<String>,
+}
+
+impl Default for EmbeddingCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CACHE_CAPACITY,
+            disk_path: None,
+            admin_key: None,
+        }
+    }
+}
+
+impl EmbeddingCacheConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            capacity: env_or("FASTEMBED_EMBEDDING_CACHE_CAPACITY", default.capacity),
+            disk_path: env_opt("FASTEMBED_EMBEDDING_CACHE_PATH"),
+            admin_key: env_opt("FASTEMBED_ADMIN_KEY"),
+        }
+    }
+}
+
+/// Content addressed cache of chunk embeddings, keyed by model, options and text hash.
+/// Disk writes are buffered and committed in one transaction by `flush`.
+pub struct EmbeddingCache {
+    memory: Option<Mutex<LruCache<String, Vec<f32>>>>,
+    disk: Option<Database>,
+    pending: Mutex<HashMap<String, Vec<f32>>>,
+    admin_key: Option<String>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl EmbeddingCache {
+    pub fn new(config: EmbeddingCacheConfig) -> Self {
+        let memory = NonZeroUsize::new(config.capacity).map(|cap| Mutex::new(LruCache::new(cap)));
+        let disk = config
+            .disk_path
+            .and_then(|path| match Database::create(&path) {
+                Ok(db) => Some(db),
+                Err(error) => {
+                    tracing::warn!(%error, path = %path.display(), "failed to open embedding cache");
+                    None
+                }
+            });
+        Self {
+            memory,
+            disk,
+            pending: Mutex::new(HashMap::new()),
+            admin_key: config.admin_key,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// A view of the cache for one model and set of preprocessing options.
+    /// Model codes may contain `/`, so key parts are separated by `|`.
+    pub fn scoped<'a>(&'a self, model: &str, options: &str) -> ScopedEmbeddingCache<'a> {
+        ScopedEmbeddingCache {
+            cache: self,
+            prefix: format!("{model}|{options}|"),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.memory.is_some() || self.disk.is_some()
+    }
+
+    /// Whether the caller sent the admin key.
+    pub fn is_admin(&self, client: &ClientId) -> bool {
+        matches!((&self.admin_key, &client.api_key), (Some(admin), Some(key)) if admin == key)
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<f32>> {
+        if let Some(memory) = &self.memory {
+            let mut memory = memory.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(embedding) = memory.get(key) {
+                return Some(embedding.clone());
+            }
+        }
+        let pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key)
+            .cloned();
+        let embedding = pending.or_else(|| self.get_from_disk(key))?;
+        if let Some(memory) = &self.memory {
+            let mut memory = memory.lock().unwrap_or_else(|e| e.into_inner());
+            memory.put(key.to_string(), embedding.clone());
+        }
+        Some(embedding)
+    }
+
+    fn get_from_disk(&self, key: &str) -> Option<Vec<f32>> {
+        let disk = self.disk.as_ref()?;
+        let read = disk.begin_read().ok()?;
+        let table = read.open_table(EMBEDDINGS_TABLE).ok()?;
+        let value = table.get(key).ok()??;
+        Some(decode_embedding(value.value()))
+    }
+
+    fn insert(&self, entries: Vec<(String, Vec<f32>)>) {
+        if let Some(memory) = &self.memory {
+            let mut memory = memory.lock().unwrap_or_else(|e| e.into_inner());
+            for (key, embedding) in &entries {
+                memory.put(key.clone(), embedding.clone());
+            }
+        }
+        if self.disk.is_some() {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            pending.extend(entries);
+        }
+    }
+
+    /// Write buffered entries to the disk tier, keeping them buffered if the write fails.
+    pub fn flush(&self) {
+        let Some(disk) = &self.disk else {
+            return;
+        };
+        let entries = std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+        if entries.is_empty() {
+            return;
+        }
+        let entries: Vec<(String, Vec<f32>)> = entries.into_iter().collect();
+        if let Err(error) = write_to_disk(disk, &entries) {
+            tracing::warn!(%error, "failed to write embedding cache");
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            for (key, embedding) in entries {
+                pending.entry(key).or_insert(embedding);
+            }
+        }
+    }
+
+    /// Remove every cached embedding of a model, returning how many were removed.
+    pub fn purge_model(&self, model: &str) -> Result<u64, CacheError> {
+        let prefix = format!("{model}|");
+        let mut purged = 0;
+        if let Some(memory) = &self.memory {
+            let mut memory = memory.lock().unwrap_or_else(|e| e.into_inner());
+            let keys: Vec<String> = memory
+                .iter()
+                .filter(|(key, _)| key.starts_with(&prefix))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in keys {
+                memory.pop(&key);
+                purged += 1;
+            }
+        }
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|key, _| !key.starts_with(&prefix));
+        if let Some(disk) = &self.disk {
+            let write = disk.begin_write()?;
+            let mut on_disk = 0;
+            {
+                let mut table = write.open_table(EMBEDDINGS_TABLE)?;
+                table.retain(|key, _| {
+                    let keep = !key.starts_with(&prefix);
+                    if !keep {
+                        on_disk += 1;
+                    }
+                    keep
+                })?;
+            }
+            write.commit()?;
+            purged = purged.max(on_disk);
+        }
+        Ok(purged)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+/// A failure of the on-disk cache, boxed as redb errors are large.
+#[derive(Debug)]
+pub struct CacheError(Box<redb::Error>);
+
+impl<E: Into<redb::Error>> From<E> for CacheError {
+    fn from(error: E) -> Self {
+        Self(Box::new(error.into()))
+    }
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+/// Cache access for one model and set of preprocessing options.
+pub struct ScopedEmbeddingCache<'a> {
+    cache: &'a EmbeddingCache,
+    prefix: String,
+}
+
+impl ScopedEmbeddingCache<'_> {
+    fn key(&self, text: &str) -> String {
+        let hash = Sha256::digest(text.as_bytes());
+        let hex: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+        format!("{}{hex}", self.prefix)
+    }
+
+    pub fn get(&self, text: &str) -> Option<Vec<f32>> {
+        let embedding = self.cache.get(&self.key(text));
+        let counter = if embedding.is_some() {
+            &self.cache.hits
+        } else {
+            &self.cache.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        embedding
+    }
+
+    pub fn insert_many<'t>(&self, entries: impl IntoIterator<Item = (&'t str, &'t Vec<f32>)>) {
+        let entries = entries
+            .into_iter()
+            .map(|(text, embedding)| (self.key(text), embedding.clone()))
+            .collect();
+        self.cache.insert(entries);
+    }
+}
+
+fn write_to_disk(disk: &Database, entries: &[(String, Vec<f32>)]) -> Result<(), CacheError> {
+    let write = disk.begin_write()?;
+    {
+        let mut table = write.open_table(EMBEDDINGS_TABLE)?;
+        for (key, embedding) in entries {
+            table.insert(key.as_str(), encode_embedding(embedding).as_slice())?;
+        }
+    }
+    write.commit()?;
+    Ok(())
+}
+
+fn encode_embedding(embedding: &[f32]) -> Vec<u8> {
+    embedding
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn decode_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+pub fn cache_routes(state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .api_route(
+            "/{*model}",
+            delete_with(purge_cache, |op| {
+                op.description("Remove every cached embedding of a model.")
+            }),
+        )
+        .with_state(state)
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PurgeCachePath {
+    /// Model code, as returned by `/embed/available-models`.
+    model: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct PurgeCacheResponse {
+    pub model: String,
+    pub purged: u64,
+}
+
+pub async fn purge_cache(
+    State(state): State<AppState>,
+    client: ClientId,
+    Path(path): Path<PurgeCachePath>,
+) -> Result<(StatusCode, Json<PurgeCacheResponse>), AppError> {
+    if !state.embedding_cache.is_admin(&client) {
+        return Err(
+            AppError::new("purging the cache requires the admin API key")
+                .with_status(StatusCode::FORBIDDEN)
+                .with_code("forbidden"),
+        );
+    }
+    let purged = state
+        .embedding_cache
+        .purge_model(&path.model)
+        .map_err(|error| {
+            AppError::new(&format!("failed to purge embedding cache: {error}"))
+                .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+    Ok((
+        StatusCode::OK,
+        Json(PurgeCacheResponse {
+            model: path.model,
+            purged,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_cache() -> EmbeddingCache {
+        EmbeddingCache::new(EmbeddingCacheConfig::default())
+    }
+
+    fn disk_cache(path: &std::path::Path) -> EmbeddingCache {
+        EmbeddingCache::new(EmbeddingCacheConfig {
+            capacity: 0,
+            disk_path: Some(path.to_path_buf()),
+            admin_key: None,
+        })
+    }
+
+    #[test]
+    fn entries_are_scoped_by_model_and_options() {
+        let cache = memory_cache();
+        let embedding = vec![1.0, 2.0];
+        cache
+            .scoped("BAAI/bge-small-en-v1.5", "whole")
+            .insert_many([("text", &embedding)]);
+        assert_eq!(
+            cache.scoped("BAAI/bge-small-en-v1.5", "whole").get("text"),
+            Some(embedding)
+        );
+        assert_eq!(
+            cache.scoped("BAAI/bge-base-en-v1.5", "whole").get("text"),
+            None
+        );
+        assert_eq!(
+            cache.scoped("BAAI/bge-small-en-v1.5", "fixed").get("text"),
+            None
+        );
+        assert_eq!(
+            cache.scoped("BAAI/bge-small-en-v1.5", "whole").get("other"),
+            None
+        );
+        assert_eq!((cache.hits(), cache.misses()), (1, 3));
+    }
+
+    #[test]
+    fn purging_a_model_keeps_models_sharing_its_prefix() {
+        let cache = memory_cache();
+        let embedding = vec![1.0];
+        cache
+            .scoped("BAAI/bge-small-en", "whole")
+            .insert_many([("text", &embedding)]);
+        cache
+            .scoped("BAAI/bge-small-en-v1.5", "whole")
+            .insert_many([("text", &embedding)]);
+        assert_eq!(cache.purge_model("BAAI/bge-small-en").unwrap(), 1);
+        assert_eq!(cache.scoped("BAAI/bge-small-en", "whole").get("text"), None);
+        assert!(cache
+            .scoped("BAAI/bge-small-en-v1.5", "whole")
+            .get("text")
+            .is_some());
+    }
+
+    #[test]
+    fn buffered_entries_are_served_and_persisted_on_flush() {
+        let path = std::env::temp_dir().join(format!("cache-{}.redb", uuid::Uuid::new_v4()));
+        let embedding = vec![0.5, -0.5];
+        {
+            let cache = disk_cache(&path);
+            cache
+                .scoped("model", "whole")
+                .insert_many([("text", &embedding)]);
+            assert_eq!(
+                cache.scoped("model", "whole").get("text"),
+                Some(embedding.clone())
+            );
+            cache.flush();
+        }
+        let reopened = disk_cache(&path);
+        assert_eq!(
+            reopened.scoped("model", "whole").get("text"),
+            Some(embedding)
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn only_the_admin_key_may_purge() {
+        let cache = EmbeddingCache::new(EmbeddingCacheConfig {
+            admin_key: Some("secret".to_string()),
+            ..EmbeddingCacheConfig::default()
+        });
+        let client = |api_key: Option<&str>| ClientId {
+            api_key: api_key.map(str::to_string),
+            ip: None,
+        };
+        assert!(cache.is_admin(&client(Some("secret"))));
+        assert!(!cache.is_admin(&client(Some("other"))));
+        assert!(!cache.is_admin(&client(None)));
+        assert!(!memory_cache().is_admin(&client(Some("secret"))));
+    }
+}
@@ -0,0 +1,551 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tree_sitter::{Language, Node, Parser};
+
+use super::{Chunk, EmbeddingError, Metadata};
+
+/// Languages source code can be chunked along its syntax tree.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeLanguage {
+    Rust,
+    Python,
+    Typescript,
+    Go,
+}
+
+impl CodeLanguage {
+    fn grammar(self) -> Language {
+        match self {
+            Self::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Self::Python => tree_sitter_python::LANGUAGE.into(),
+            Self::Typescript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Self::Go => tree_sitter_go::LANGUAGE.into(),
+        }
+    }
+
+    /// Functions, types and other items that make up a chunk of their own.
+    fn is_definition(self, kind: &str) -> bool {
+        let kinds: &[&str] = match self {
+            Self::Rust => &[
+                "function_item",
+                "function_signature_item",
+                "struct_item",
+                "enum_item",
+                "union_item",
+                "trait_item",
+                "impl_item",
+                "mod_item",
+                "macro_definition",
+                "const_item",
+                "static_item",
+                "type_item",
+            ],
+            Self::Python => &["function_definition", "class_definition"],
+            Self::Typescript => &[
+                "function_declaration",
+                "generator_function_declaration",
+                "class_declaration",
+                "abstract_class_declaration",
+                "interface_declaration",
+                "enum_declaration",
+                "type_alias_declaration",
+                "method_definition",
+                "internal_module",
+            ],
+            Self::Go => &[
+                "function_declaration",
+                "method_declaration",
+                "type_declaration",
+            ],
+        };
+        kinds.contains(&kind)
+    }
+
+    /// Definitions whose members are chunked separately when the whole is too long.
+    fn is_container(self, kind: &str) -> bool {
+        let kinds: &[&str] = match self {
+            Self::Rust => &["impl_item", "trait_item", "mod_item"],
+            Self::Python => &["class_definition"],
+            Self::Typescript => &[
+                "class_declaration",
+                "abstract_class_declaration",
+                "interface_declaration",
+                "internal_module",
+            ],
+            Self::Go => &[],
+        };
+        kinds.contains(&kind)
+    }
+
+    /// Nodes wrapping a definition, such as decorators or `export`, and the field holding it.
+    fn wrapped_field(self, kind: &str) -> Option<&'static str> {
+        match (self, kind) {
+            (Self::Python, "decorated_definition") => Some("definition"),
+            (Self::Typescript, "export_statement") => Some("declaration"),
+            _ => None,
+        }
+    }
+
+    /// Nodes kept with the definition that follows them, besides comments.
+    fn is_leading(self, kind: &str) -> bool {
+        matches!(
+            (self, kind),
+            (Self::Rust, "attribute_item") | (Self::Typescript, "decorator")
+        )
+    }
+
+    fn separator(self) -> &'static str {
+        match self {
+            Self::Rust => "::",
+            _ => ".",
+        }
+    }
+}
+
+/// A byte and line range of the source, lines counted from 1.
+#[derive(Clone, Copy)]
+struct Span {
+    start_byte: usize,
+    end_byte: usize,
+    start_line: usize,
+    end_line: usize,
+}
+
+impl Span {
+    fn of(node: Node) -> Self {
+        Self {
+            start_byte: node.start_byte(),
+            end_byte: node.end_byte(),
+            start_line: node.start_position().row + 1,
+            end_line: node.end_position().row + 1,
+        }
+    }
+
+    fn to(self, end: Span) -> Self {
+        Self {
+            end_byte: end.end_byte,
+            end_line: end.end_line,
+            ..self
+        }
+    }
+}
+
+/// An enclosing definition: its signature, prepended to every chunk inside it, and its name.
+#[derive(Clone)]
+struct Scope {
+    signature: String,
+    name: Option<String>,
+}
+
+struct CodeChunker<'a> {
+    language: CodeLanguage,
+    source: &'a str,
+    max_chars: usize,
+    chunks: Vec<Chunk>,
+}
+
+/// Split source code at function, class and other definition boundaries. Each chunk
+/// starts with the signatures of the definitions enclosing it and reports its line
+/// range and symbol in its metadata. Definitions whose chunk would be longer than
+/// `max_chars` characters, signatures included, are split into their members where they
+/// have any, and otherwise between lines. A single line is never split, so a chunk only
+/// exceeds `max_chars` when one line and its signatures do.
+pub fn code_chunks(
+    text: &str,
+    language: CodeLanguage,
+    max_chars: usize,
+) -> Result<Vec<Chunk>, EmbeddingError> {
+    let mut parser = Parser::new();
+    parser
+        .set_language(&language.grammar())
+        .map_err(|error| EmbeddingError::ChunkingFailed(error.to_string()))?;
+    let tree = parser.parse(text, None).ok_or_else(|| {
+        EmbeddingError::ChunkingFailed(format!("failed to parse {language:?} source"))
+    })?;
+    let mut chunker = CodeChunker {
+        language,
+        source: text,
+        max_chars,
+        chunks: Vec::new(),
+    };
+    chunker.children(tree.root_node(), &[]);
+    Ok(chunker.chunks)
+}
+
+impl CodeChunker<'_> {
+    /// Chunk the children of `node`, grouping code between definitions together.
+    fn children(&mut self, node: Node, scopes: &[Scope]) {
+        let mut cursor = node.walk();
+        let children: Vec<Node> = node.named_children(&mut cursor).collect();
+        let mut loose: Option<Span> = None;
+        let mut leading: Option<Span> = None;
+        for child in children {
+            if child.is_extra() || self.language.is_leading(child.kind()) {
+                leading = Some(leading.map_or(Span::of(child), |span| span.to(Span::of(child))));
+                continue;
+            }
+            match self.definition(child) {
+                Some(definition) => {
+                    if let Some(span) = loose.take() {
+                        self.emit_lines(span, scopes, None, None, None);
+                    }
+                    let span = leading
+                        .take()
+                        .unwrap_or(Span::of(child))
+                        .to(Span::of(child));
+                    self.definition_chunks(span, child, definition, scopes);
+                }
+                None => {
+                    let span = leading
+                        .take()
+                        .unwrap_or(Span::of(child))
+                        .to(Span::of(child));
+                    loose = match loose {
+                        Some(current) if self.fits(current.to(span), scopes, None) => {
+                            Some(current.to(span))
+                        }
+                        Some(current) => {
+                            self.emit_lines(current, scopes, None, None, None);
+                            Some(span)
+                        }
+                        None => Some(span),
+                    };
+                }
+            }
+        }
+        let loose = match (loose, leading) {
+            (Some(current), Some(span)) => Some(current.to(span)),
+            (current, span) => current.or(span),
+        };
+        if let Some(span) = loose {
+            self.emit_lines(span, scopes, None, None, None);
+        }
+    }
+
+    /// The definition `node` is or wraps, if any.
+    fn definition<'t>(&self, node: Node<'t>) -> Option<Node<'t>> {
+        if self.language.is_definition(node.kind()) {
+            return Some(node);
+        }
+        let field = self.language.wrapped_field(node.kind())?;
+        node.child_by_field_name(field)
+            .filter(|inner| self.language.is_definition(inner.kind()))
+    }
+
+    fn definition_chunks(&mut self, span: Span, node: Node, definition: Node, scopes: &[Scope]) {
+        let name = self.name(definition);
+        let kind = definition.kind();
+        if self.fits(span, scopes, None) {
+            self.emit(span, scopes, None, name.as_deref(), Some(kind));
+            return;
+        }
+        let signature = self.signature(node, definition);
+        match definition.child_by_field_name("body") {
+            Some(body) if self.language.is_container(kind) => {
+                let mut scopes = scopes.to_vec();
+                scopes.push(Scope { signature, name });
+                self.children(body, &scopes);
+            }
+            _ => self.emit_lines(span, scopes, Some(&signature), name.as_deref(), Some(kind)),
+        }
+    }
+
+    /// The text of a definition up to its body, e.g. `impl Display for Id`.
+    fn signature(&self, node: Node, definition: Node) -> String {
+        let end = definition
+            .child_by_field_name("body")
+            .map_or(definition.end_byte(), |body| body.start_byte());
+        let signature = self.source[node.start_byte()..end].trim();
+        match definition.child_by_field_name("body") {
+            Some(_) => signature.to_string(),
+            None => signature.lines().next().unwrap_or_default().to_string(),
+        }
+    }
+
+    fn name(&self, definition: Node) -> Option<String> {
+        let node = match (self.language, definition.kind()) {
+            (CodeLanguage::Rust, "impl_item") => definition.child_by_field_name("type"),
+            (CodeLanguage::Go, "type_declaration") => {
+                let mut cursor = definition.walk();
+                let spec = definition.named_children(&mut cursor).next();
+                spec.and_then(|spec| spec.child_by_field_name("name"))
+            }
+            _ => definition.child_by_field_name("name"),
+        }?;
+        Some(self.source[node.byte_range()].to_string())
+    }
+
+    /// Emit a span, splitting it between lines when longer than `max_chars`. Pieces after
+    /// the first are prefixed with `signature` as well as the enclosing scopes.
+    fn emit_lines(
+        &mut self,
+        span: Span,
+        scopes: &[Scope],
+        signature: Option<&str>,
+        name: Option<&str>,
+        kind: Option<&str>,
+    ) {
+        let mut piece: Option<Span> = None;
+        let mut start_byte = span.start_byte;
+        for (i, line) in self.source[span.start_byte..span.end_byte]
+            .split_inclusive('\n')
+            .enumerate()
+        {
+            let line_span = Span {
+                start_byte,
+                end_byte: start_byte + line.len(),
+                start_line: span.start_line + i,
+                end_line: span.start_line + i,
+            };
+            start_byte = line_span.end_byte;
+            piece = match piece {
+                Some(current)
+                    if self.fits(
+                        current.to(line_span),
+                        scopes,
+                        signature.filter(|_| current.start_byte != span.start_byte),
+                    ) =>
+                {
+                    Some(current.to(line_span))
+                }
+                Some(current) => {
+                    let signature = signature.filter(|_| current.start_byte != span.start_byte);
+                    self.emit(current, scopes, signature, name, kind);
+                    Some(line_span)
+                }
+                None => Some(line_span),
+            };
+        }
+        if let Some(current) = piece {
+            let signature = signature.filter(|_| current.start_byte != span.start_byte);
+            self.emit(current, scopes, signature, name, kind);
+        }
+    }
+
+    /// The code of a span, as it appears in its chunk.
+    fn code(&self, span: Span) -> &str {
+        self.source[span.start_byte..span.end_byte].trim_end()
+    }
+
+    /// Whether the chunk [`Self::emit`] would make of a span is within `max_chars`.
+    fn fits(&self, span: Span, scopes: &[Scope], signature: Option<&str>) -> bool {
+        let prefix: usize = scopes
+            .iter()
+            .map(|scope| scope.signature.as_str())
+            .chain(signature)
+            .map(|line| line.chars().count() + 1)
+            .sum();
+        prefix + self.code(span).chars().count() <= self.max_chars
+    }
+
+    fn emit(
+        &mut self,
+        span: Span,
+        scopes: &[Scope],
+        signature: Option<&str>,
+        name: Option<&str>,
+        kind: Option<&str>,
+    ) {
+        let code = self.code(span);
+        if code.trim().is_empty() {
+            return;
+        }
+        let text = scopes
+            .iter()
+            .map(|scope| scope.signature.as_str())
+            .chain(signature)
+            .chain([code])
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut metadata = Metadata::new();
+        metadata.insert("start_line".to_string(), span.start_line.into());
+        metadata.insert("end_line".to_string(), span.end_line.into());
+        if let Some(symbol) = self.symbol(scopes, name) {
+            metadata.insert("symbol".to_string(), symbol.into());
+        }
+        if let Some(kind) = kind {
+            metadata.insert("kind".to_string(), kind.into());
+        }
+        self.chunks.push(Chunk {
+            text,
+            metadata: Some(metadata),
+        });
+    }
+
+    /// The path of the symbol through its enclosing scopes, e.g. `Client::embed`.
+    fn symbol(&self, scopes: &[Scope], name: Option<&str>) -> Option<String> {
+        let name = name?;
+        let mut path: Vec<&str> = scopes
+            .iter()
+            .filter_map(|scope| scope.name.as_deref())
+            .collect();
+        path.push(name);
+        Some(path.join(self.language.separator()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(start_line, end_line, symbol)` of every chunk.
+    fn spans(chunks: &[Chunk]) -> Vec<(u64, u64, Option<&str>)> {
+        chunks
+            .iter()
+            .map(|chunk| {
+                let metadata = chunk.metadata.as_ref().unwrap();
+                (
+                    metadata["start_line"].as_u64().unwrap(),
+                    metadata["end_line"].as_u64().unwrap(),
+                    metadata.get("symbol").and_then(|symbol| symbol.as_str()),
+                )
+            })
+            .collect()
+    }
+
+    fn chunk(source: &[&str], language: CodeLanguage, max_chars: usize) -> Vec<Chunk> {
+        let chunks = code_chunks(&source.join("\n"), language, max_chars).unwrap();
+        for chunk in &chunks {
+            assert!(
+                chunk.text.chars().count() <= max_chars,
+                "{:?} is longer than {max_chars} characters",
+                chunk.text
+            );
+        }
+        chunks
+    }
+
+    const RUST: &[&str] = &[
+        "use std::fmt;",
+        "",
+        "/// An id.",
+        "struct Id(u32);",
+        "",
+        "impl Id {",
+        "    fn new(id: u32) -> Self {",
+        "        Self(id)",
+        "    }",
+        "",
+        "    fn get(&self) -> u32 {",
+        "        self.0",
+        "    }",
+        "}",
+    ];
+
+    #[test]
+    fn rust_definitions_are_chunks_with_their_comments() {
+        let chunks = chunk(RUST, CodeLanguage::Rust, 1000);
+        assert_eq!(
+            spans(&chunks),
+            vec![(1, 1, None), (3, 4, Some("Id")), (6, 14, Some("Id"))]
+        );
+        assert_eq!(chunks[1].text, "/// An id.\nstruct Id(u32);");
+        assert_eq!(chunks[2].metadata.as_ref().unwrap()["kind"], "impl_item");
+    }
+
+    #[test]
+    fn long_rust_impls_are_split_into_methods() {
+        let chunks = chunk(RUST, CodeLanguage::Rust, 60);
+        assert_eq!(
+            spans(&chunks),
+            vec![
+                (1, 1, None),
+                (3, 4, Some("Id")),
+                (7, 9, Some("Id::new")),
+                (11, 13, Some("Id::get")),
+            ]
+        );
+        assert_eq!(
+            chunks[2].text,
+            "impl Id\nfn new(id: u32) -> Self {\n        Self(id)\n    }"
+        );
+    }
+
+    #[test]
+    fn long_functions_are_split_between_lines_with_their_signature() {
+        let source = &[
+            "fn long() {",
+            "    let a = 1;",
+            "    let b = 2;",
+            "    let c = 3;",
+            "}",
+        ];
+        let chunks = chunk(source, CodeLanguage::Rust, 30);
+        assert_eq!(
+            spans(&chunks),
+            vec![
+                (1, 2, Some("long")),
+                (3, 3, Some("long")),
+                (4, 5, Some("long")),
+            ]
+        );
+        assert_eq!(chunks[1].text, "fn long()\n    let b = 2;");
+    }
+
+    #[test]
+    fn python_classes_are_split_into_methods() {
+        let source = &[
+            "class Parser(Base):",
+            "    def parse(self, text):",
+            "        return text",
+            "",
+            "    def feed(self, data):",
+            "        self.buffer += data",
+        ];
+        let chunks = chunk(source, CodeLanguage::Python, 70);
+        assert_eq!(
+            spans(&chunks),
+            vec![(2, 3, Some("Parser.parse")), (5, 6, Some("Parser.feed"))]
+        );
+        assert!(chunks[0].text.starts_with("class Parser(Base):\ndef parse"));
+    }
+
+    #[test]
+    fn max_chars_counts_characters_not_bytes() {
+        let source = &["def f():", "    return \"éééééééééé\""];
+        let chunks = chunk(source, CodeLanguage::Python, 35);
+        assert_eq!(spans(&chunks), vec![(1, 2, Some("f"))]);
+    }
+
+    #[test]
+    fn typescript_exports_are_unwrapped() {
+        let source = &[
+            "import { x } from \"y\";",
+            "",
+            "export class Store {",
+            "  get(key: string): string {",
+            "    return key;",
+            "  }",
+            "}",
+        ];
+        let chunks = chunk(source, CodeLanguage::Typescript, 1000);
+        assert_eq!(spans(&chunks), vec![(1, 1, None), (3, 7, Some("Store"))]);
+        let chunks = chunk(source, CodeLanguage::Typescript, 66);
+        assert_eq!(
+            spans(&chunks),
+            vec![(1, 1, None), (4, 6, Some("Store.get"))]
+        );
+        assert!(chunks[1].text.starts_with("export class Store\nget("));
+    }
+
+    #[test]
+    fn go_types_and_methods_are_chunks() {
+        let source = &[
+            "package main",
+            "",
+            "type Id struct {",
+            "\tvalue int",
+            "}",
+            "",
+            "func (id Id) Get() int {",
+            "\treturn id.value",
+            "}",
+        ];
+        let chunks = chunk(source, CodeLanguage::Go, 1000);
+        assert_eq!(
+            spans(&chunks),
+            vec![(1, 1, None), (3, 5, Some("Id")), (7, 9, Some("Get"))]
+        );
+    }
+}
@@ -1,4 +1,9 @@
+pub mod cache;
+pub mod code;
+pub mod preprocess;
 pub mod routes;
+pub mod semantic;
+pub mod truncation;
 
 pub use fastembed::{
     EmbeddingModel, InitOptions, InitOptionsUserDefined, ModelInfo, TextEmbedding,
@@ -8,9 +13,128 @@ pub use fastembed::{
 use reqwest;
 
 pub use routes::*;
+
+use crate::server::{config::env_or, logging::log_raw_text};
+use cache::ScopedEmbeddingCache;
+use code::{code_chunks, CodeLanguage};
 use schemars::JsonSchema;
+use semantic::{semantic_chunks, Breakpoint};
 use serde::{Deserialize, Serialize};
-use std::{io::Read, path::PathBuf, time::Duration};
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokenizers::{Encoding, PostProcessor, Tokenizer};
+use truncation::Truncation;
+
+const DEFAULT_CHUNK_SIZE: usize = 10;
+const DEFAULT_CHUNK_OVERLAP: usize = 3;
+const DEFAULT_SEMANTIC_MAX_TOKENS: usize = 256;
+const DEFAULT_CODE_MAX_CHARS: usize = 1500;
+
+fn default_semantic_max_tokens() -> usize {
+    DEFAULT_SEMANTIC_MAX_TOKENS
+}
+
+fn default_code_max_chars() -> usize {
+    DEFAULT_CODE_MAX_CHARS
+}
+
+/// How documents are split before embedding.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChunkingStrategy {
+    /// Overlapping windows of `size` characters.
+    Fixed { size: usize, overlap: usize },
+    /// Embed each document as a single chunk.
+    Whole,
+    /// Sentences grouped by meaning: boundaries go where adjacent sentences are
+    /// dissimilar under the current model, or where a chunk would exceed `max_tokens`.
+    Semantic {
+        breakpoint: Breakpoint,
+        #[serde(default = "default_semantic_max_tokens")]
+        max_tokens: usize,
+    },
+    /// Source code split at function, class and other definition boundaries, with the
+    /// enclosing signatures prepended and line ranges reported in chunk metadata.
+    Code {
+        language: CodeLanguage,
+        /// Definitions longer than this are split into their members, or between lines.
+        #[serde(default = "default_code_max_chars")]
+        max_chars: usize,
+    },
+}
+
+impl Default for ChunkingStrategy {
+    fn default() -> Self {
+        Self::Fixed {
+            size: DEFAULT_CHUNK_SIZE,
+            overlap: DEFAULT_CHUNK_OVERLAP,
+        }
+    }
+}
+
+impl ChunkingStrategy {
+    /// Options affecting chunk text, part of the embedding cache key.
+    pub fn cache_key(&self) -> String {
+        match self {
+            Self::Fixed { size, overlap } => format!("chunk={size},overlap={overlap}"),
+            Self::Whole => "whole".to_string(),
+            Self::Semantic {
+                breakpoint,
+                max_tokens,
+            } => format!("semantic={breakpoint:?},max_tokens={max_tokens}"),
+            Self::Code {
+                language,
+                max_chars,
+            } => format!("code={language:?},max_chars={max_chars}"),
+        }
+    }
+
+    /// Reasons the options can't be used, if any.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Fixed { size, overlap } if *size == 0 || overlap >= size => Err(format!(
+                "chunk size must be positive and larger than the overlap, got size {size} and overlap {overlap}"
+            )),
+            Self::Semantic { max_tokens: 0, .. } => {
+                Err("max_tokens must be positive".to_string())
+            }
+            Self::Code { max_chars: 0, .. } => Err("max_chars must be positive".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    fn chunk(&self, model: &mut TextEmbedding, text: &str) -> Result<Vec<Chunk>, EmbeddingError> {
+        let texts = match self {
+            Self::Fixed { size, overlap } => chunk_with_overlap(text, *size, *overlap),
+            Self::Whole => vec![text.to_string()],
+            Self::Semantic {
+                breakpoint,
+                max_tokens,
+            } => semantic_chunks(model, text, *breakpoint, *max_tokens)?,
+            Self::Code {
+                language,
+                max_chars,
+            } => return code_chunks(text, *language, *max_chars),
+        };
+        Ok(texts
+            .into_iter()
+            .map(|text| Chunk {
+                text,
+                metadata: None,
+            })
+            .collect())
+    }
+}
+
+/// A piece of a document to embed, with metadata from strategies that know where it came from.
+pub struct Chunk {
+    pub text: String,
+    pub metadata: Option<Metadata>,
+}
 
 pub enum HFEmbeddingModelOrUserDefinedModel {
     HuggingFace(EmbeddingModel),
@@ -21,16 +145,97 @@ pub enum ModelSource {
     HuggingFace,
     Local(Box<UserDefinedEmbeddingModel>),
 }
+/// A document id: an integer or any string, such as a UUID or a path.
+#[derive(Clone, Deserialize, Serialize, JsonSchema, Debug, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum DocumentId {
+    Int(i64),
+    String(String),
+}
+
+impl std::fmt::Display for DocumentId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Int(id) => write!(f, "{id}"),
+            Self::String(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+impl From<i64> for DocumentId {
+    fn from(id: i64) -> Self {
+        Self::Int(id)
+    }
+}
+
+impl From<String> for DocumentId {
+    fn from(id: String) -> Self {
+        Self::String(id)
+    }
+}
+
+/// Opaque caller data carried alongside a document and echoed back unchanged.
+pub type Metadata = serde_json::Map<String, serde_json::Value>;
+
 #[derive(Clone, Deserialize, Serialize, JsonSchema, Debug)]
 pub struct EmbeddingRequestUnit {
-    pub id: i32,
+    pub id: DocumentId,
     pub text_to_embed: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
 }
 
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct EmbeddingResponseObject {
-    id: i32,
+    id: DocumentId,
     embeddings: Vec<Vec<f32>>, //Vec of vecs, so we can store multiple embeddings for each document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<Metadata>,
+    /// Metadata for each embedding in order, e.g. line ranges from code chunking.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    chunks: Vec<Metadata>,
+    /// Tokens in each chunk in order, including special tokens.
+    #[serde(default)]
+    tokens: Vec<usize>,
+    /// Whether any chunk was longer than the model accepts and had content dropped.
+    #[serde(default)]
+    truncated: bool,
+}
+
+impl EmbeddingResponseObject {
+    pub fn id(&self) -> &DocumentId {
+        &self.id
+    }
+
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Per-chunk metadata, empty unless the chunking strategy reports any.
+    pub fn chunks(&self) -> &[Metadata] {
+        &self.chunks
+    }
+
+    pub fn tokens(&self) -> &[usize] {
+        &self.tokens
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The id, embeddings and metadata, consuming the object.
+    pub fn into_parts(self) -> (DocumentId, Vec<Vec<f32>>, Option<Metadata>) {
+        (self.id, self.embeddings, self.metadata)
+    }
+
+    pub fn embeddings(&self) -> &[Vec<f32>] {
+        &self.embeddings
+    }
+
+    pub fn into_embeddings(self) -> Vec<Vec<f32>> {
+        self.embeddings
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
@@ -39,56 +244,207 @@ pub struct EmbeddingResponse {
     total_time_ms: u128,        //total time in milliseconds
     time_per_document_ms: u128, //time per document in milliseconds
     embeddings: Vec<EmbeddingResponseObject>,
+    cache_hits: u32,   //chunks served from the embedding cache
+    cache_misses: u32, //chunks sent to the model because they were not cached
+    dedup_ratio: f32,  //fraction of chunks that duplicated another chunk in the request
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    preprocessing: Vec<String>, //preprocessing steps applied before chunking, in order
+}
+
+impl EmbeddingResponse {
+    pub fn number_of_documents(&self) -> u32 {
+        self.number_of_documents
+    }
+
+    /// Total number of chunks embedded across all documents.
+    pub fn number_of_chunks(&self) -> usize {
+        self.embeddings.iter().map(|doc| doc.embeddings.len()).sum()
+    }
+
+    pub fn cache_hits(&self) -> u32 {
+        self.cache_hits
+    }
+
+    pub fn cache_misses(&self) -> u32 {
+        self.cache_misses
+    }
+
+    pub fn dedup_ratio(&self) -> f32 {
+        self.dedup_ratio
+    }
+
+    pub fn into_embeddings(self) -> Vec<EmbeddingResponseObject> {
+        self.embeddings
+    }
+
+    /// Record the preprocessing steps that were applied to the documents.
+    pub fn set_preprocessing(&mut self, steps: Vec<String>) {
+        self.preprocessing = steps;
+    }
+
+    pub fn preprocessing(&self) -> &[String] {
+        &self.preprocessing
+    }
+
+    pub fn total_time(&self) -> Duration {
+        Duration::from_millis(self.total_time_ms as u64)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 struct EmbeddingTracker {
-    id: i32,
+    id: DocumentId,
+    metadata: Option<Metadata>,
     num_docs: u32,
     text: Vec<String>,
+    chunks: Vec<Metadata>,
+    truncated: bool,
+}
+
+/// The distinct chunks of a request in first-seen order, and for every chunk
+/// the index of its distinct copy.
+struct Deduplicated<'a> {
+    unique: Vec<&'a str>,
+    chunk_to_unique: Vec<usize>,
+}
+
+impl<'a> Deduplicated<'a> {
+    fn new(chunks: &'a [String]) -> Self {
+        let mut unique_index: HashMap<&str, usize> = HashMap::new();
+        let mut unique: Vec<&str> = Vec::new();
+        let chunk_to_unique = chunks
+            .iter()
+            .map(|chunk| {
+                *unique_index.entry(chunk.as_str()).or_insert_with(|| {
+                    unique.push(chunk.as_str());
+                    unique.len() - 1
+                })
+            })
+            .collect();
+        Self {
+            unique,
+            chunk_to_unique,
+        }
+    }
+
+    /// Fraction of chunks that duplicated an earlier chunk.
+    fn ratio(&self) -> f32 {
+        if self.chunk_to_unique.is_empty() {
+            0.0
+        } else {
+            1.0 - self.unique.len() as f32 / self.chunk_to_unique.len() as f32
+        }
+    }
 }
 
 pub fn embed_documents(
     model: &mut TextEmbedding,
     request: Vec<EmbeddingRequestUnit>,
-) -> EmbeddingResponse {
+    chunking: &ChunkingStrategy,
+    truncation: Truncation,
+    cache: Option<&ScopedEmbeddingCache>,
+) -> Result<EmbeddingResponse, EmbeddingError> {
     let start = tokio::time::Instant::now();
     let num_docs: u32 = request.len() as u32;
-    let ids: Vec<_> = request.iter().map(|x| x.id).collect();
-    let texts: Vec<String> = request.iter().map(|x| x.text_to_embed.clone()).collect();
     let mut embedding_trackers: Vec<EmbeddingTracker> = Vec::new();
-    for (i, text) in texts.iter().enumerate() {
-        let chunked_texts = chunk_with_overlap(text, 10, 3);
-        let tracker = EmbeddingTracker {
-            id: ids[i],
-            num_docs: chunked_texts.len() as u32,
-            text: chunked_texts,
-        };
-        embedding_trackers.push(tracker);
+    tracing::info_span!("chunking").in_scope(|| {
+        for unit in request {
+            let mut text = Vec::new();
+            let mut chunks = Vec::new();
+            let mut truncated = false;
+            for chunk in chunking.chunk(model, &unit.text_to_embed)? {
+                let fitted = truncation.fit(&model.tokenizer, chunk.text)?;
+                truncated |= fitted.truncated;
+                if let Some(metadata) = chunk.metadata {
+                    chunks.extend(fitted.texts.iter().map(|_| metadata.clone()));
+                }
+                text.extend(fitted.texts);
+            }
+            let tracker = EmbeddingTracker {
+                id: unit.id,
+                metadata: unit.metadata,
+                num_docs: text.len() as u32,
+                text,
+                chunks,
+                truncated,
+            };
+            embedding_trackers.push(tracker);
+        }
+        Ok::<_, EmbeddingError>(())
+    })?;
+    tracing::debug!(
+        documents = num_docs,
+        chunks = embedding_trackers
+            .iter()
+            .map(|tracker| tracker.num_docs)
+            .sum::<u32>(),
+        "chunked documents"
+    );
+    if log_raw_text() {
+        tracing::debug!(?embedding_trackers, "chunked document text");
     }
-    println!("{:?}", embedding_trackers);
     let flattened_chunked_texts: Vec<String> = embedding_trackers
         .iter()
         .flat_map(|tracker| tracker.text.clone())
         .collect();
 
-    let embeddings_vec: Vec<Vec<f32>> = model.embed(flattened_chunked_texts, None).unwrap();
-    // Rebuild the embeddings into the original structure
+    // Identical chunks are only looked up and embedded once
+    let deduplicated = Deduplicated::new(&flattened_chunked_texts);
+    let dedup_ratio = deduplicated.ratio();
+    let Deduplicated {
+        unique: unique_chunks,
+        chunk_to_unique,
+    } = deduplicated;
+
+    // Look up every unique chunk in the cache and only send the misses to the model
+    let mut unique_embeddings: Vec<Option<Vec<f32>>> = unique_chunks
+        .iter()
+        .map(|chunk| cache.and_then(|cache| cache.get(chunk)))
+        .collect();
+    let missing: Vec<usize> = (0..unique_embeddings.len())
+        .filter(|&i| unique_embeddings[i].is_none())
+        .collect();
+    let cache_hits = (unique_embeddings.len() - missing.len()) as u32;
+    let cache_misses = if cache.is_some() {
+        missing.len() as u32
+    } else {
+        0
+    };
+    let texts_to_embed: Vec<&str> = missing.iter().map(|&i| unique_chunks[i]).collect();
+    let unique_tokens = token_counts(model, &unique_chunks)?;
+    let embeddings_vec: Vec<Vec<f32>> = if texts_to_embed.is_empty() {
+        Vec::new()
+    } else {
+        tracing::info_span!("inference", chunks = texts_to_embed.len()).in_scope(|| {
+            model
+                .embed(&texts_to_embed, None)
+                .map_err(|error| EmbeddingError::InferenceFailed(error.to_string()))
+        })?
+    };
+    if let Some(cache) = cache {
+        cache.insert_many(texts_to_embed.iter().copied().zip(embeddings_vec.iter()));
+    }
+    for (i, embedding) in missing.into_iter().zip(embeddings_vec) {
+        unique_embeddings[i] = Some(embedding);
+    }
+
+    // Fan the unique embeddings back out and rebuild the original structure
+    let mut chunk_tokens = chunk_to_unique.iter().map(|&i| unique_tokens[i]);
+    let mut chunk_embeddings = chunk_to_unique
+        .iter()
+        .filter_map(|&i| unique_embeddings[i].clone());
     let mut embeddings: Vec<EmbeddingResponseObject> = Vec::new();
     for tracker in embedding_trackers {
-        let mut embeddings_for_doc: Vec<Vec<f32>> = Vec::new();
-        for chunk in tracker.text {
-            let chunk_embeddings: Vec<f32> = embeddings_vec
-                .iter()
-                .take(chunk.len())
-                .cloned()
-                .collect::<Vec<Vec<f32>>>()
-                .concat();
-            embeddings_for_doc.push(chunk_embeddings);
-        }
+        let embeddings_for_doc: Vec<Vec<f32>> =
+            chunk_embeddings.by_ref().take(tracker.text.len()).collect();
+        let tokens: Vec<usize> = chunk_tokens.by_ref().take(tracker.text.len()).collect();
         let embeddings_object = EmbeddingResponseObject {
             id: tracker.id,
             embeddings: embeddings_for_doc,
+            metadata: tracker.metadata,
+            chunks: tracker.chunks,
+            truncated: tracker.truncated,
+            tokens,
         };
         embeddings.push(embeddings_object);
     }
@@ -102,9 +458,106 @@ pub fn embed_documents(
         embeddings,
         number_of_documents: num_docs,
         total_time_ms: duration.as_millis(),
-        time_per_document_ms: (duration.as_millis()) / num_docs as u128,
+        cache_hits,
+        cache_misses,
+        dedup_ratio,
+        preprocessing: Vec::new(),
+        time_per_document_ms: duration
+            .as_millis()
+            .checked_div(num_docs as u128)
+            .unwrap_or(0),
     };
-    response
+    Ok(response)
+}
+
+/// Maximum tokens the model accepts in one input, longer inputs are handled by a [`Truncation`] policy.
+pub fn max_length(model: &TextEmbedding) -> Option<usize> {
+    model
+        .tokenizer
+        .get_truncation()
+        .map(|truncation| truncation.max_length)
+}
+
+/// Tokens the tokenizer produces for a text, including special tokens and any
+/// tokens the model's truncation would cut off.
+fn token_length(model: &TextEmbedding, text: &str) -> Result<usize, EmbeddingError> {
+    tokenizer_length(&model.tokenizer, text)
+}
+
+/// See [`token_length`]. Content is encoded without special tokens, which would
+/// otherwise be added again to every overflowing piece, and they are counted once.
+fn tokenizer_length(tokenizer: &Tokenizer, text: &str) -> Result<usize, EmbeddingError> {
+    let encoding = tokenizer
+        .encode(text, false)
+        .map_err(|error| EmbeddingError::TokenizationFailed(error.to_string()))?;
+    let content: usize = std::iter::once(&encoding)
+        .chain(encoding.get_overflowing())
+        .map(Encoding::len)
+        .sum();
+    let special = tokenizer
+        .get_post_processor()
+        .map_or(0, |processor| processor.added_tokens(false));
+    Ok(content + special)
+}
+
+/// Count the tokens the model's tokenizer produces for each text, including special tokens.
+#[tracing::instrument(name = "tokenize", skip_all, fields(texts = texts.len()))]
+pub fn token_counts<S: AsRef<str>>(
+    model: &TextEmbedding,
+    texts: &[S],
+) -> Result<Vec<usize>, EmbeddingError> {
+    texts
+        .iter()
+        .map(|text| token_length(model, text.as_ref()))
+        .collect()
+}
+
+/// Total tokens across every text, see [`token_counts`].
+pub fn count_tokens<S: AsRef<str>>(
+    model: &TextEmbedding,
+    texts: &[S],
+) -> Result<usize, EmbeddingError> {
+    Ok(token_counts(model, texts)?.into_iter().sum())
+}
+
+/// A text as the model's tokenizer sees it.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct Tokenization {
+    /// Token ids the model receives, including special tokens.
+    pub ids: Vec<u32>,
+    pub tokens: Vec<String>,
+    /// Byte range of each token in the text, `[0, 0]` for special tokens.
+    pub offsets: Vec<(usize, usize)>,
+    /// Tokens in the whole text, including any cut off by truncation.
+    pub count: usize,
+    /// Whether the text is longer than the model accepts, so only `ids` are embedded.
+    pub truncated: bool,
+}
+
+pub fn tokenize(model: &TextEmbedding, text: &str) -> Result<Tokenization, EmbeddingError> {
+    let encoding = model
+        .tokenizer
+        .encode(text, true)
+        .map_err(|error| EmbeddingError::TokenizationFailed(error.to_string()))?;
+    Ok(Tokenization {
+        ids: encoding.get_ids().to_vec(),
+        tokens: encoding.get_tokens().to_vec(),
+        offsets: encoding.get_offsets().to_vec(),
+        count: token_length(model, text)?,
+        truncated: !encoding.get_overflowing().is_empty(),
+    })
+}
+
+/// Turn token ids back into text.
+pub fn detokenize(
+    model: &TextEmbedding,
+    ids: &[u32],
+    skip_special_tokens: bool,
+) -> Result<String, EmbeddingError> {
+    model
+        .tokenizer
+        .decode(ids, skip_special_tokens)
+        .map_err(|error| EmbeddingError::TokenizationFailed(error.to_string()))
 }
 
 fn chunk_with_overlap(text: &str, chunk_size: usize, overlap: usize) -> Vec<String> {
@@ -130,21 +583,47 @@ pub fn get_current_model_info(
     match current_model {
         HFEmbeddingModelOrUserDefinedModel::HuggingFace(model) => {
             let model_info: &ModelInfo<EmbeddingModel> =
-                TextEmbedding::get_model_info(model).expect("Model not found");
+                TextEmbedding::get_model_info(model).map_err(|_| ModelNotFoundError)?;
             Ok(JSONModelInfo {
                 name: model_info.model_code.to_string(),
                 dimension: model_info.dim as u32,
                 description: model_info.description.clone(),
+                max_length: downloaded_max_length(model_info),
             })
         }
-        HFEmbeddingModelOrUserDefinedModel::UserDefined(model) => todo!(), // {Ok(JSONModelInfo {
-                                                                           //     name: model.model_code.to_string(),
-                                                                           //     dimension: model.dim as u32,
-                                                                           //     description: model.description.clone(),
-                                                                           // })},
+        // User defined models carry no name, dimension or description
+        HFEmbeddingModelOrUserDefinedModel::UserDefined(_) => Err(ModelNotFoundError),
     }
 }
 
+/// The maximum length fastembed gives a model's tokenizer, read from its tokenizer config
+/// when the model was already downloaded.
+fn downloaded_max_length(model_info: &ModelInfo<EmbeddingModel>) -> Option<usize> {
+    // fastembed downloads into HF_HOME when it is set
+    let cache = std::env::var("HF_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| cache_dir());
+    let max_length = InitOptions::new(model_info.model.clone()).max_length;
+    cached_max_length(&cache, &model_info.model_code, max_length)
+}
+
+/// The smaller of `max_length` and the `model_max_length` of a model's cached tokenizer config.
+fn cached_max_length(cache: &Path, model_code: &str, max_length: usize) -> Option<usize> {
+    let snapshots = cache
+        .join(format!("models--{}", model_code.replace('/', "--")))
+        .join("snapshots");
+    std::fs::read_dir(snapshots)
+        .ok()?
+        .flatten()
+        .find_map(|snapshot| {
+            let config = std::fs::read(snapshot.path().join("tokenizer_config.json")).ok()?;
+            let config: serde_json::Value = serde_json::from_slice(&config).ok()?;
+            // Some configs use a huge float to mean unlimited, the cast saturates
+            let model_max_length = config["model_max_length"].as_f64()? as usize;
+            Some(max_length.min(model_max_length))
+        })
+}
+
 pub fn get_model_by_string(proposed_model: String) -> Result<EmbeddingModel, ModelNotFoundError> {
     let models_info = TextEmbedding::list_supported_models();
     let models: Vec<ModelInfo<EmbeddingModel>> = models_info
@@ -165,6 +644,7 @@ pub fn get_available_models() -> Vec<JSONModelInfo> {
     let json_models_info: Vec<JSONModelInfo> = models_info
         .into_iter()
         .map(|model_info| JSONModelInfo {
+            max_length: downloaded_max_length(&model_info),
             name: model_info.model_code.to_string(),
             dimension: model_info.dim as u32,
             description: model_info.description.clone(),
@@ -178,6 +658,9 @@ pub struct JSONModelInfo {
     pub name: String,
     pub dimension: u32,
     pub description: String,
+    /// Maximum tokens per input, known once the model is downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -189,28 +672,77 @@ impl std::fmt::Display for ModelNotFoundError {
     }
 }
 
-pub fn init_text_embedding() -> TextEmbedding {
+/// Failures on the embedding path, each with a stable code returned to clients.
+#[derive(Debug, Clone)]
+pub enum EmbeddingError {
+    /// The model could not be loaded or is unavailable.
+    ModelNotLoaded(String),
+    TokenizationFailed(String),
+    InferenceFailed(String),
+    /// An input has more tokens than the model accepts.
+    InputTooLong {
+        tokens: usize,
+        max: usize,
+    },
+    /// Too many requests are already waiting for the model.
+    Overloaded,
+    /// A document could not be split with the requested strategy.
+    ChunkingFailed(String),
+}
+
+impl EmbeddingError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ModelNotLoaded(_) => "model_not_loaded",
+            Self::TokenizationFailed(_) => "tokenization_failed",
+            Self::InferenceFailed(_) => "inference_failed",
+            Self::InputTooLong { .. } => "input_too_long",
+            Self::Overloaded => "overloaded",
+            Self::ChunkingFailed(_) => "chunking_failed",
+        }
+    }
+}
+
+impl std::fmt::Display for EmbeddingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::ModelNotLoaded(error) => write!(f, "The model could not be loaded: {error}"),
+            Self::TokenizationFailed(error) => write!(f, "Tokenization failed: {error}"),
+            Self::InferenceFailed(error) => write!(f, "Inference failed: {error}"),
+            Self::InputTooLong { tokens, max } => write!(
+                f,
+                "An input of {tokens} tokens exceeds the model's maximum of {max}"
+            ),
+            Self::Overloaded => write!(f, "Too many requests are waiting for the model"),
+            Self::ChunkingFailed(error) => write!(f, "Chunking failed: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for EmbeddingError {}
+
+pub fn init_text_embedding() -> Result<TextEmbedding, EmbeddingError> {
     let model_name: EmbeddingModel = EmbeddingModel::AllMiniLML6V2;
     new_text_embedding(&model_name)
 }
 
-pub fn new_text_embedding(model_name: &EmbeddingModel) -> TextEmbedding {
-    const CACHE_DIR: &str = "./.fastembed_cache";
-    TextEmbedding::try_new(
-        InitOptions::new(model_name.clone()).with_cache_dir(Into::into(CACHE_DIR)),
-    )
-    .expect("Can't load model")
+pub const DEFAULT_CACHE_DIR: &str = "./.fastembed_cache";
+
+/// Directory models are downloaded to, overridable with `FASTEMBED_CACHE_DIR`.
+pub fn cache_dir() -> PathBuf {
+    env_or("FASTEMBED_CACHE_DIR", PathBuf::from(DEFAULT_CACHE_DIR))
+}
+
+pub fn new_text_embedding(model_name: &EmbeddingModel) -> Result<TextEmbedding, EmbeddingError> {
+    TextEmbedding::try_new(InitOptions::new(model_name.clone()).with_cache_dir(cache_dir()))
+        .map_err(|error| EmbeddingError::ModelNotLoaded(error.to_string()))
 }
 
-pub fn new_text_embedding_user_defined(model: UserDefinedEmbeddingModel) -> TextEmbedding {
-    todo!()
-    // TextEmbedding::try_new_from_user_defined(
-    //     model,
-    //     InitOptionsUserDefined {
-    //         ..Default::default()
-    //     },
-    // )
-    // .expect("Can't load model")
+pub fn new_text_embedding_user_defined(
+    model: UserDefinedEmbeddingModel,
+) -> Result<TextEmbedding, EmbeddingError> {
+    TextEmbedding::try_new_from_user_defined(model, InitOptionsUserDefined::default())
+        .map_err(|error| EmbeddingError::ModelNotLoaded(error.to_string()))
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
@@ -273,6 +805,15 @@ pub enum LocalOrRemoteFileReadError {
     Remote(reqwest::Error),
 }
 
+impl std::fmt::Display for LocalOrRemoteFileReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Local(error) => write!(f, "{error}"),
+            Self::Remote(error) => write!(f, "{error}"),
+        }
+    }
+}
+
 // fn remote() -> UserDefinedEmbeddingModel {
 //     // use the remote urls to create a UserDefinedEmbeddingModel
 //     let user_defined_model = UserDefinedEmbeddingModel {
@@ -299,3 +840,123 @@ pub enum LocalOrRemoteFileReadError {
 //     };
 //     user_defined_model
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    /// A word-level tokenizer over `a`, `b`, `é` and `日本` that adds `[CLS]` and `[SEP]` and
+    /// truncates to `max_length` tokens, special tokens included.
+    pub(crate) fn tokenizer(max_length: usize) -> Tokenizer {
+        let json = serde_json::json!({
+            "version": "1.0",
+            "truncation": {
+                "direction": "Right",
+                "max_length": max_length,
+                "strategy": "LongestFirst",
+                "stride": 0
+            },
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": {
+                "type": "BertProcessing",
+                "sep": ["[SEP]", 2],
+                "cls": ["[CLS]", 1]
+            },
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": {
+                    "[UNK]": 0, "[CLS]": 1, "[SEP]": 2, "a": 3, "b": 4, "é": 5, "日本": 6
+                },
+                "unk_token": "[UNK]"
+            }
+        });
+        json.to_string().parse().unwrap()
+    }
+
+    #[test]
+    fn max_length_is_read_from_cached_tokenizer_configs() {
+        let cache = std::env::temp_dir().join(format!("models-{}", uuid::Uuid::new_v4()));
+        let write_config = |model: &str, config: serde_json::Value| {
+            let snapshot = cache.join(model).join("snapshots").join("0123abcd");
+            std::fs::create_dir_all(&snapshot).unwrap();
+            std::fs::write(snapshot.join("tokenizer_config.json"), config.to_string()).unwrap();
+        };
+        write_config(
+            "models--org--short",
+            serde_json::json!({ "model_max_length": 256 }),
+        );
+        write_config(
+            "models--org--unlimited",
+            serde_json::json!({ "model_max_length": 1e30 }),
+        );
+        assert_eq!(cached_max_length(&cache, "org/short", 512), Some(256));
+        assert_eq!(cached_max_length(&cache, "org/unlimited", 512), Some(512));
+        assert_eq!(cached_max_length(&cache, "org/missing", 512), None);
+        std::fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn special_tokens_are_counted_once() {
+        let tokenizer = tokenizer(4);
+        assert_eq!(tokenizer_length(&tokenizer, "").unwrap(), 2);
+        assert_eq!(tokenizer_length(&tokenizer, "a b").unwrap(), 4);
+        // Ten words overflow into five pieces of two words, each with its own [CLS] and [SEP]
+        let long = ["a b"; 5].join(" ");
+        assert_eq!(
+            tokenizer
+                .encode(long.as_str(), true)
+                .unwrap()
+                .get_overflowing()
+                .len(),
+            4
+        );
+        assert_eq!(tokenizer_length(&tokenizer, &long).unwrap(), 12);
+    }
+
+    #[test]
+    fn document_ids_are_echoed_exactly() {
+        for json in [
+            "42",
+            "\"42\"",
+            "\"67E55044-10B1-426F-9247-BB680E5FE0C8\"",
+            "\"67e55044-10b1-426f-9247-bb680e5fe0c8\"",
+            "\"docs/readme.md\"",
+        ] {
+            let id: DocumentId = serde_json::from_str(json).unwrap();
+            assert_eq!(serde_json::to_string(&id).unwrap(), json);
+        }
+        let id: DocumentId =
+            serde_json::from_str("\"67E55044-10B1-426F-9247-BB680E5FE0C8\"").unwrap();
+        assert_eq!(id.to_string(), "67E55044-10B1-426F-9247-BB680E5FE0C8");
+    }
+
+    #[test]
+    fn duplicate_chunks_map_to_their_first_copy() {
+        let texts = chunks(&["a", "b", "a", "c", "b", "a"]);
+        let deduplicated = Deduplicated::new(&texts);
+        assert_eq!(deduplicated.unique, vec!["a", "b", "c"]);
+        assert_eq!(deduplicated.chunk_to_unique, vec![0, 1, 0, 2, 1, 0]);
+        assert_eq!(deduplicated.ratio(), 0.5);
+    }
+
+    #[test]
+    fn dedup_ratio_is_zero_without_duplicates() {
+        let texts = chunks(&["a", "b", "c"]);
+        assert_eq!(Deduplicated::new(&texts).ratio(), 0.0);
+        assert_eq!(Deduplicated::new(&[]).ratio(), 0.0);
+    }
+
+    #[test]
+    fn dedup_is_exact_match_only() {
+        let texts = chunks(&["Hello", "hello", "hello "]);
+        assert_eq!(Deduplicated::new(&texts).unique.len(), 3);
+    }
+}
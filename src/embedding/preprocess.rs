@@ -0,0 +1,229 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+use super::EmbeddingRequestUnit;
+use crate::server::config::env_opt;
+
+const URL_MASK: &str = "[URL]";
+const EMAIL_MASK: &str = "[EMAIL]";
+
+static URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\b(?:https?://|www\.)[^\s<>]+").unwrap());
+static EMAIL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+").unwrap());
+static WHITESPACE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+").unwrap());
+
+/// Text cleaning applied to every document before chunking. Steps run in the order
+/// of the fields and every step is off unless enabled.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Preprocessing {
+    /// Remove control characters other than whitespace.
+    pub strip_control: bool,
+    /// Apply Unicode NFKC normalization.
+    pub nfkc: bool,
+    pub lowercase: bool,
+    /// Replace email addresses with `[EMAIL]`.
+    pub mask_emails: bool,
+    /// Replace URLs with `[URL]`.
+    pub mask_urls: bool,
+    /// Collapse runs of whitespace into a single space and trim the ends.
+    pub collapse_whitespace: bool,
+    /// Truncate to this many characters.
+    pub max_chars: Option<usize>,
+}
+
+impl Preprocessing {
+    /// Names of the enabled steps, in the order they run.
+    pub fn steps(&self) -> Vec<String> {
+        [
+            (self.strip_control, "strip_control"),
+            (self.nfkc, "nfkc"),
+            (self.lowercase, "lowercase"),
+            (self.mask_emails, "mask_emails"),
+            (self.mask_urls, "mask_urls"),
+            (self.collapse_whitespace, "collapse_whitespace"),
+            (self.max_chars.is_some(), "max_chars"),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, step)| step.to_string())
+        .collect()
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        if self.strip_control {
+            text.retain(|c| !c.is_control() || c.is_whitespace());
+        }
+        if self.nfkc {
+            text = text.nfkc().collect();
+        }
+        if self.lowercase {
+            text = text.to_lowercase();
+        }
+        if self.mask_emails {
+            text = EMAIL.replace_all(&text, EMAIL_MASK).into_owned();
+        }
+        if self.mask_urls {
+            text = URL.replace_all(&text, URL_MASK).into_owned();
+        }
+        if self.collapse_whitespace {
+            text = WHITESPACE.replace_all(text.trim(), " ").into_owned();
+        }
+        if let Some(max_chars) = self.max_chars {
+            if let Some((end, _)) = text.char_indices().nth(max_chars) {
+                text.truncate(end);
+            }
+        }
+        text
+    }
+}
+
+/// Preprocessing applied by default to requests for a model, keyed by model code.
+#[derive(Clone, Debug, Default)]
+pub struct PreprocessingProfiles {
+    pub profiles: HashMap<String, Preprocessing>,
+}
+
+impl PreprocessingProfiles {
+    /// Read profiles from `FASTEMBED_PREPROCESSING_PROFILES`, a JSON object mapping
+    /// model codes to preprocessing options.
+    pub fn from_env() -> Self {
+        let profiles = env_opt::<String>("FASTEMBED_PREPROCESSING_PROFILES")
+            .and_then(|json| {
+                serde_json::from_str(&json)
+                    .map_err(|error| {
+                        tracing::warn!(%error, "invalid FASTEMBED_PREPROCESSING_PROFILES, ignoring")
+                    })
+                    .ok()
+            })
+            .unwrap_or_default();
+        Self { profiles }
+    }
+
+    pub fn for_model(&self, model: &str) -> Option<&Preprocessing> {
+        self.profiles.get(model)
+    }
+
+    /// Clean every document with `requested`, or the profile of `model` when none is
+    /// given, returning the steps applied.
+    pub fn apply(
+        &self,
+        model: &str,
+        requested: Option<&Preprocessing>,
+        data: &mut [EmbeddingRequestUnit],
+    ) -> Vec<String> {
+        let Some(preprocessing) = requested.or_else(|| self.for_model(model)) else {
+            return Vec::new();
+        };
+        for unit in data {
+            unit.text_to_embed = preprocessing.apply(&unit.text_to_embed);
+        }
+        preprocessing.steps()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedding::DocumentId;
+
+    const MODEL: &str = "BAAI/bge-small-en-v1.5";
+
+    fn documents(texts: &[&str]) -> Vec<EmbeddingRequestUnit> {
+        texts
+            .iter()
+            .enumerate()
+            .map(|(i, text)| EmbeddingRequestUnit {
+                id: DocumentId::Int(i as i64),
+                text_to_embed: text.to_string(),
+                metadata: None,
+            })
+            .collect()
+    }
+
+    fn texts(data: &[EmbeddingRequestUnit]) -> Vec<&str> {
+        data.iter()
+            .map(|unit| unit.text_to_embed.as_str())
+            .collect()
+    }
+
+    fn profiles() -> PreprocessingProfiles {
+        let lowercase = Preprocessing {
+            lowercase: true,
+            collapse_whitespace: true,
+            ..Preprocessing::default()
+        };
+        PreprocessingProfiles {
+            profiles: HashMap::from([(MODEL.to_string(), lowercase)]),
+        }
+    }
+
+    #[test]
+    fn steps_run_in_order() {
+        let preprocessing = Preprocessing {
+            strip_control: true,
+            mask_emails: true,
+            mask_urls: true,
+            collapse_whitespace: true,
+            max_chars: Some(30),
+            ..Preprocessing::default()
+        };
+        assert_eq!(
+            preprocessing.apply("  mail\u{7}  a@b.io  at https://b.io/x  now "),
+            "mail [EMAIL] at [URL] now"
+        );
+        assert_eq!(
+            preprocessing.steps(),
+            vec![
+                "strip_control",
+                "mask_emails",
+                "mask_urls",
+                "collapse_whitespace",
+                "max_chars"
+            ]
+        );
+    }
+
+    #[test]
+    fn max_chars_cuts_between_characters() {
+        let preprocessing = Preprocessing {
+            max_chars: Some(2),
+            ..Preprocessing::default()
+        };
+        assert_eq!(preprocessing.apply("héllo"), "hé");
+    }
+
+    #[test]
+    fn the_model_profile_applies_when_none_is_requested() {
+        let mut data = documents(&["Hello   World"]);
+        let steps = profiles().apply(MODEL, None, &mut data);
+        assert_eq!(texts(&data), vec!["hello world"]);
+        assert_eq!(steps, vec!["lowercase", "collapse_whitespace"]);
+    }
+
+    #[test]
+    fn requested_preprocessing_replaces_the_profile() {
+        let mut data = documents(&["Hello   World"]);
+        let requested = Preprocessing {
+            collapse_whitespace: true,
+            ..Preprocessing::default()
+        };
+        let steps = profiles().apply(MODEL, Some(&requested), &mut data);
+        assert_eq!(texts(&data), vec!["Hello World"]);
+        assert_eq!(steps, vec!["collapse_whitespace"]);
+    }
+
+    #[test]
+    fn other_models_are_left_alone() {
+        let mut data = documents(&["Hello   World"]);
+        let steps = profiles().apply("other/model", None, &mut data);
+        assert_eq!(texts(&data), vec!["Hello   World"]);
+        assert!(steps.is_empty());
+    }
+}
@@ -7,27 +7,69 @@ use aide::{
 };
 
 use axum::{extract::State, http::StatusCode};
+use fastembed::TextEmbedding;
 
 use schemars::JsonSchema;
 
-use crate::{server::extractors::Json, server::state::AppState};
+use crate::{
+    collections::DistanceMetric,
+    server::errors::AppError,
+    server::extractors::{ClientId, Json},
+    server::limits::Fields,
+    server::state::AppState,
+};
 
 use super::{
-    embed_documents, get_available_models, get_current_model_info, get_model_by_string,
-    EmbeddingRequestUnit, EmbeddingResponse, HFEmbeddingModelOrUserDefinedModel, JSONModelInfo,
-    ModelNotFoundError,
+    detokenize, embed_documents, get_available_models, get_current_model_info, get_model_by_string,
+    preprocess::Preprocessing, token_counts, tokenize, truncation::Truncation, ChunkingStrategy,
+    DocumentId, EmbeddingError, EmbeddingRequestUnit, EmbeddingResponse,
+    HFEmbeddingModelOrUserDefinedModel, JSONModelInfo, ModelNotFoundError, Tokenization,
 };
 use axum_macros::debug_handler;
 use serde::{Deserialize, Serialize};
+use tracing::Instrument;
 
-#[derive(Deserialize, JsonSchema, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct EmbeddingRequest {
-    data: Vec<EmbeddingRequestUnit>,
+    #[schemars(length(min = 1))]
+    pub data: Vec<EmbeddingRequestUnit>,
+    /// Cleaning applied before chunking, defaults to the current model's profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preprocessing: Option<Preprocessing>,
+    /// How documents are split, defaults to fixed windows of 10 characters overlapping by 3.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunking: Option<ChunkingStrategy>,
+    /// What to do with chunks longer than the model accepts, defaults to rejecting the request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncation: Option<Truncation>,
 }
 
 pub fn embed_routes(state: AppState) -> ApiRouter {
     ApiRouter::new()
         .api_route("/generate", post_with(embed, all_docs))
+        .api_route("/similarity", post_with(similarity, all_docs))
+        .api_route(
+            "/tokenize",
+            post_with(tokenize_text, |op| {
+                op.description(
+                    "Token ids, strings and byte offsets of a text under the current model's tokenizer.",
+                )
+            }),
+        )
+        .api_route(
+            "/detokenize",
+            post_with(detokenize_ids, |op| {
+                op.description("Turn token ids back into text.")
+            }),
+        )
+        .api_route(
+            "/count-tokens",
+            post_with(count_text_tokens, |op| {
+                op.description(
+                    "Count the tokens of each text and whether the model would truncate it, without running inference.",
+                )
+            }),
+        )
         .api_route("/model-info", get_with(model_info, all_docs))
         .api_route("/set-model-name", post_with(url_set_model_name, all_docs))
         .api_route("/available-models", get_with(available_models, all_docs))
@@ -40,33 +82,428 @@ fn all_docs(op: TransformOperation) -> TransformOperation {
 }
 
 #[debug_handler]
+#[tracing::instrument(
+    skip_all,
+    fields(
+        model = %state.model_info.name,
+        documents = payload.data.len(),
+        chunks,
+        tokens,
+        total_time_ms,
+    )
+)]
 pub async fn embed(
     State(state): State<AppState>,
+    client: ClientId,
     Json(payload): Json<EmbeddingRequest>,
-) -> (StatusCode, Json<EmbeddingResponse>) {
-    let mut embedding_model = state
-        .text_embedding
-        .lock()
-        .expect("Fail to get lock on model");
-    let embeddings = embed_documents(&mut embedding_model, payload.data);
-    (StatusCode::ACCEPTED, Json(embeddings))
-}
-
-pub async fn model_info(State(state): State<AppState>) -> (StatusCode, Json<JSONModelInfo>) {
-    let model_guard = state.model.lock().unwrap();
-    let model = &*model_guard;
-    let model_info = get_current_model_info(model);
-    match model_info {
-        Ok(model) => (StatusCode::OK, Json(model)),
-        Err(ModelNotFoundError) => (
-            StatusCode::NOT_FOUND,
-            Json(JSONModelInfo {
-                name: "".to_string(),
-                dimension: 0,
-                description: "".to_string(),
-            }),
-        ),
+) -> Result<(StatusCode, Json<EmbeddingResponse>), AppError> {
+    let chunking = payload.chunking.unwrap_or_default();
+    chunking
+        .validate()
+        .map_err(|error| AppError::new(&error).with_status(StatusCode::UNPROCESSABLE_ENTITY))?;
+    let settings = EmbedSettings::new(Fields::DATA)
+        .with_chunking(chunking)
+        .with_truncation(payload.truncation.unwrap_or_default())
+        .with_preprocessing(payload.preprocessing);
+    let embeddings = embed_with_state(&state, &client, payload.data, settings).await?;
+    Ok((StatusCode::ACCEPTED, Json(embeddings)))
+}
+
+/// How [`embed_with_state`] treats one request's documents.
+#[derive(Clone, Debug)]
+pub struct EmbedSettings {
+    /// Request fields the documents came from, named in limit violations.
+    pub fields: Fields,
+    pub chunking: ChunkingStrategy,
+    pub truncation: Truncation,
+    /// Cleaning applied before chunking, the current model's profile when `None`.
+    pub preprocessing: Option<Preprocessing>,
+}
+
+impl EmbedSettings {
+    /// Each document embedded whole with the model's profile, long ones truncated at
+    /// the end. Endpoints without a `truncation` option keep working on long inputs.
+    pub fn new(fields: Fields) -> Self {
+        Self {
+            fields,
+            chunking: ChunkingStrategy::Whole,
+            truncation: Truncation::TruncateEnd,
+            preprocessing: None,
+        }
     }
+
+    pub fn with_chunking(mut self, chunking: ChunkingStrategy) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    pub fn with_truncation(mut self, truncation: Truncation) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
+    pub fn with_preprocessing(mut self, preprocessing: Option<Preprocessing>) -> Self {
+        self.preprocessing = preprocessing;
+        self
+    }
+}
+
+impl From<EmbeddingError> for AppError {
+    fn from(error: EmbeddingError) -> Self {
+        let status = match error {
+            EmbeddingError::ModelNotLoaded(_) | EmbeddingError::Overloaded => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            EmbeddingError::TokenizationFailed(_)
+            | EmbeddingError::InputTooLong { .. }
+            | EmbeddingError::ChunkingFailed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            EmbeddingError::InferenceFailed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let app_error = AppError::new(&error.to_string())
+            .with_status(status)
+            .with_code(error.code());
+        match error {
+            EmbeddingError::Overloaded => app_error.with_retry_after(1),
+            _ => app_error,
+        }
+    }
+}
+
+/// Embed documents with the current model, applying preprocessing, rate limits,
+/// the cache and metrics. Shared by every endpoint that needs embeddings.
+///
+/// Requests wait for the model without holding a runtime thread, then run
+/// inference on the blocking pool.
+pub async fn embed_with_state(
+    state: &AppState,
+    client: &ClientId,
+    mut data: Vec<EmbeddingRequestUnit>,
+    settings: EmbedSettings,
+) -> Result<EmbeddingResponse, AppError> {
+    let steps = state.preprocessing_profiles.apply(
+        &state.model_info.name,
+        settings.preprocessing.as_ref(),
+        &mut data,
+    );
+    state.limits.check_documents(&settings.fields, &data)?;
+    if state.health.is_overloaded(state.metrics.queue_depth()) {
+        return Err(EmbeddingError::Overloaded.into());
+    }
+    let queued = state.metrics.enqueue();
+    let (owned_state, client) = (state.clone(), client.clone());
+    let mut embeddings = with_model(state, move |model| {
+        drop(queued);
+        embed_blocking(&owned_state, &client, model, data, &settings)
+    })
+    .await??;
+    embeddings.set_preprocessing(steps);
+    Ok(embeddings)
+}
+
+/// Run `f` with exclusive use of the model on the blocking pool, waiting for
+/// the model without holding a runtime thread.
+pub async fn with_model<T, F>(state: &AppState, f: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut TextEmbedding) -> T + Send + 'static,
+{
+    let permit = state
+        .embedding_permits
+        .clone()
+        .acquire_owned()
+        .instrument(tracing::info_span!("queue"))
+        .await
+        .map_err(|error| EmbeddingError::ModelNotLoaded(error.to_string()))?;
+    let model = state.text_embedding.clone();
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        // A panic while holding the lock leaves the model itself usable
+        let mut model = model.lock().unwrap_or_else(|e| e.into_inner());
+        span.in_scope(|| f(&mut model))
+    })
+    .await
+    .map_err(|error| EmbeddingError::InferenceFailed(error.to_string()).into())
+}
+
+fn embed_blocking(
+    state: &AppState,
+    client: &ClientId,
+    embedding_model: &mut TextEmbedding,
+    data: Vec<EmbeddingRequestUnit>,
+    settings: &EmbedSettings,
+) -> Result<EmbeddingResponse, AppError> {
+    let EmbedSettings {
+        fields,
+        chunking,
+        truncation,
+        ..
+    } = settings;
+    let texts: Vec<&str> = data
+        .iter()
+        .map(|unit| unit.text_to_embed.as_str())
+        .collect();
+    let token_counts = token_counts(embedding_model, &texts)?;
+    state.limits.check_tokens(fields, &token_counts)?;
+    let tokens: usize = token_counts.iter().sum();
+    state
+        .rate_limiter
+        .check_embedding(client, data.len() as u64, tokens as u64)?;
+    let number_of_documents = data.len();
+    let cache = state.embedding_cache.is_enabled().then(|| {
+        state
+            .embedding_cache
+            .scoped(&state.model_info.name, &chunking.cache_key())
+    });
+    let embeddings = embed_documents(embedding_model, data, chunking, *truncation, cache.as_ref())?;
+    state.health.record_embedding();
+    state.metrics.observe_embedding(
+        number_of_documents,
+        embeddings.number_of_chunks(),
+        tokens,
+        embeddings.total_time(),
+    );
+    state
+        .metrics
+        .observe_cache(embeddings.cache_hits(), embeddings.cache_misses());
+    let span = tracing::Span::current();
+    span.record("chunks", embeddings.number_of_chunks());
+    span.record("tokens", tokens);
+    span.record("total_time_ms", embeddings.total_time().as_millis() as u64);
+    tracing::info!("embedded documents");
+    Ok(embeddings)
+}
+
+/// Embed each text as a single chunk, returning one vector per text in order.
+pub async fn embed_texts(
+    state: &AppState,
+    client: &ClientId,
+    texts: Vec<String>,
+    fields: Fields,
+) -> Result<Vec<Vec<f32>>, AppError> {
+    let data = texts
+        .into_iter()
+        .enumerate()
+        .map(|(i, text_to_embed)| EmbeddingRequestUnit {
+            id: DocumentId::Int(i as i64),
+            text_to_embed,
+            metadata: None,
+        })
+        .collect();
+    let embeddings = embed_with_state(state, client, data, EmbedSettings::new(fields)).await?;
+    Ok(embeddings
+        .into_embeddings()
+        .into_iter()
+        .map(|object| {
+            object
+                .into_embeddings()
+                .into_iter()
+                .next()
+                .unwrap_or_default()
+        })
+        .collect())
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct SimilarityRequest {
+    sources: Vec<String>,
+    targets: Vec<String>,
+    #[serde(default)]
+    metric: DistanceMetric,
+    /// Return only the `top_k` closest targets per source instead of the full matrix.
+    top_k: Option<usize>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SimilarityMatch {
+    /// Index of the target in the request.
+    target: usize,
+    score: f32,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SimilarityResponse {
+    metric: DistanceMetric,
+    /// Score of every source (row) against every target (column).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scores: Option<Vec<Vec<f32>>>,
+    /// Closest targets of every source, closest first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<Vec<Vec<SimilarityMatch>>>,
+}
+
+/// Score every source against every target, keeping the `top_k` closest targets per source if set.
+fn compare_embeddings(
+    sources: &[Vec<f32>],
+    targets: &[Vec<f32>],
+    metric: DistanceMetric,
+    top_k: Option<usize>,
+) -> SimilarityResponse {
+    let scores: Vec<Vec<f32>> = sources
+        .iter()
+        .map(|source| {
+            targets
+                .iter()
+                .map(|target| metric.score(source, target))
+                .collect()
+        })
+        .collect();
+    match top_k {
+        Some(k) => SimilarityResponse {
+            metric,
+            scores: None,
+            top_k: Some(
+                scores
+                    .into_iter()
+                    .map(|row| {
+                        let mut matches: Vec<SimilarityMatch> = row
+                            .into_iter()
+                            .enumerate()
+                            .map(|(target, score)| SimilarityMatch { target, score })
+                            .collect();
+                        matches.sort_by(|a, b| metric.compare(a.score, b.score));
+                        matches.truncate(k);
+                        matches
+                    })
+                    .collect(),
+            ),
+        },
+        None => SimilarityResponse {
+            metric,
+            scores: Some(scores),
+            top_k: None,
+        },
+    }
+}
+
+pub async fn similarity(
+    State(state): State<AppState>,
+    client: ClientId,
+    Json(payload): Json<SimilarityRequest>,
+) -> Result<(StatusCode, Json<SimilarityResponse>), AppError> {
+    let number_of_sources = payload.sources.len();
+    let texts = payload.sources.into_iter().chain(payload.targets).collect();
+    let fields = Fields::Pair {
+        first: "sources",
+        first_len: number_of_sources,
+        second: "targets",
+    };
+    let mut embeddings = embed_texts(&state, &client, texts, fields).await?;
+    let targets = embeddings.split_off(number_of_sources);
+    let sources = embeddings;
+    let response = compare_embeddings(&sources, &targets, payload.metric, payload.top_k);
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct TokenizeRequest {
+    text: String,
+}
+
+pub async fn tokenize_text(
+    State(state): State<AppState>,
+    Json(payload): Json<TokenizeRequest>,
+) -> Result<(StatusCode, Json<Tokenization>), AppError> {
+    let tokenization = with_model(&state, move |model| tokenize(model, &payload.text)).await??;
+    Ok((StatusCode::OK, Json(tokenization)))
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct DetokenizeRequest {
+    ids: Vec<u32>,
+    /// Leave out special tokens such as `[CLS]` and `[SEP]`.
+    #[serde(default)]
+    skip_special_tokens: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DetokenizeResponse {
+    text: String,
+}
+
+pub async fn detokenize_ids(
+    State(state): State<AppState>,
+    Json(payload): Json<DetokenizeRequest>,
+) -> Result<(StatusCode, Json<DetokenizeResponse>), AppError> {
+    let text = with_model(&state, move |model| {
+        detokenize(model, &payload.ids, payload.skip_special_tokens)
+    })
+    .await??;
+    Ok((StatusCode::OK, Json(DetokenizeResponse { text })))
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct CountTokensRequest {
+    texts: Vec<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct TokenCount {
+    /// Tokens including special tokens and any the model would cut off.
+    tokens: usize,
+    truncated: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct CountTokensResponse {
+    /// Counts for each text in order.
+    counts: Vec<TokenCount>,
+    total: usize,
+    /// Maximum tokens the model accepts in one input, if it has a limit.
+    max_length: Option<usize>,
+}
+
+/// Counts are for each text whole, before chunking.
+pub async fn count_text_tokens(
+    State(state): State<AppState>,
+    Json(payload): Json<CountTokensRequest>,
+) -> Result<(StatusCode, Json<CountTokensResponse>), AppError> {
+    let max_length = state.model_info.max_length;
+    let counts: Vec<TokenCount> =
+        with_model(&state, move |model| token_counts(model, &payload.texts))
+            .await??
+            .into_iter()
+            .map(|tokens| TokenCount {
+                tokens,
+                truncated: max_length.is_some_and(|max| tokens > max),
+            })
+            .collect();
+    let total = counts.iter().map(|count| count.tokens).sum();
+    Ok((
+        StatusCode::OK,
+        Json(CountTokensResponse {
+            counts,
+            total,
+            max_length,
+        }),
+    ))
+}
+
+impl From<ModelNotFoundError> for AppError {
+    fn from(error: ModelNotFoundError) -> Self {
+        AppError::new(&error.to_string())
+            .with_status(StatusCode::NOT_FOUND)
+            .with_code("model_not_found")
+    }
+}
+
+pub async fn model_info(
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<JSONModelInfo>), AppError> {
+    let model_info = {
+        let model_guard = state.model.lock().unwrap_or_else(|e| e.into_inner());
+        match &*model_guard {
+            // User defined models are only described by what was measured at load time
+            HFEmbeddingModelOrUserDefinedModel::UserDefined(_) => state.model_info.clone(),
+            model => get_current_model_info(model)?,
+        }
+    };
+    let model_info = JSONModelInfo {
+        // Only the model loaded at startup serves embeddings
+        max_length: state.model_info.max_length,
+        ..model_info
+    };
+    Ok((StatusCode::OK, Json(model_info)))
 }
 
 #[debug_handler]
@@ -81,7 +518,7 @@ pub async fn url_set_model_name(
     match model_result {
         Ok(model) => {
             // If the model is found, update the state
-            let mut model_guard = state.model.lock().unwrap();
+            let mut model_guard = state.model.lock().unwrap_or_else(|e| e.into_inner());
             *model_guard = HFEmbeddingModelOrUserDefinedModel::HuggingFace(model);
             StatusCode::CREATED
         }
@@ -103,3 +540,80 @@ pub struct SetModelName {
 pub async fn hello_world() -> (StatusCode, Json<String>) {
     (StatusCode::OK, Json("Hello!".to_string()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vectors() -> Vec<Vec<f32>> {
+        vec![
+            vec![1.0, 0.0],
+            vec![4.0, 3.0],
+            vec![3.0, 4.0],
+            vec![0.0, 1.0],
+        ]
+    }
+
+    fn top_k(response: &SimilarityResponse) -> Vec<Vec<(usize, f32)>> {
+        response
+            .top_k
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|row| row.iter().map(|m| (m.target, m.score)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn matrix_of_a_set_against_itself_is_symmetric() {
+        let vectors = vectors();
+        for metric in [
+            DistanceMetric::Cosine,
+            DistanceMetric::Dot,
+            DistanceMetric::L2,
+        ] {
+            let response = compare_embeddings(&vectors, &vectors, metric, None);
+            assert!(response.top_k.is_none());
+            let scores = response.scores.unwrap();
+            assert_eq!(scores.len(), 4);
+            for (i, row) in scores.iter().enumerate() {
+                assert_eq!(row.len(), 4);
+                for (j, score) in row.iter().enumerate() {
+                    assert_eq!(*score, scores[j][i], "{metric:?} {i} {j}");
+                }
+            }
+        }
+        let scores = compare_embeddings(&vectors[..1], &vectors, DistanceMetric::Cosine, None)
+            .scores
+            .unwrap();
+        assert_eq!(scores, vec![vec![1.0, 0.8, 0.6, 0.0]]);
+    }
+
+    #[test]
+    fn top_k_keeps_the_closest_targets() {
+        let vectors = vectors();
+        let sources = [vectors[0].clone(), vectors[3].clone()];
+        let response = compare_embeddings(&sources, &vectors, DistanceMetric::Cosine, Some(2));
+        assert!(response.scores.is_none());
+        assert_eq!(
+            top_k(&response),
+            vec![vec![(0, 1.0), (1, 0.8)], vec![(3, 1.0), (2, 0.8)]]
+        );
+        // Smaller distances are closer under L2
+        let response = compare_embeddings(&sources[..1], &vectors, DistanceMetric::L2, Some(1));
+        assert_eq!(top_k(&response), vec![vec![(0, 0.0)]]);
+    }
+
+    #[test]
+    fn top_k_beyond_the_targets_returns_them_all() {
+        let vectors = vectors();
+        let response =
+            compare_embeddings(&vectors[..1], &vectors, DistanceMetric::Cosine, Some(10));
+        assert_eq!(
+            top_k(&response),
+            vec![vec![(0, 1.0), (1, 0.8), (2, 0.6), (3, 0.0)]]
+        );
+        let response = compare_embeddings(&vectors[..1], &[], DistanceMetric::Cosine, Some(3));
+        assert_eq!(top_k(&response), vec![vec![]]);
+    }
+}
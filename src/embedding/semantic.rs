@@ -0,0 +1,240 @@
+use fastembed::TextEmbedding;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{token_length, EmbeddingError};
+use crate::collections::DistanceMetric;
+
+/// Where semantic chunking places boundaries between adjacent sentences.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Breakpoint {
+    /// Split where cosine similarity between adjacent sentences drops below this value.
+    Threshold(f32),
+    /// Split at this percentage of sentence gaps with the lowest similarity, 0 to 100.
+    Percentile(f32),
+}
+
+impl Breakpoint {
+    /// Whether each gap between adjacent sentences is a boundary.
+    fn boundaries(&self, similarities: &[f32]) -> Vec<bool> {
+        match *self {
+            Self::Threshold(threshold) => similarities
+                .iter()
+                .map(|&similarity| similarity < threshold)
+                .collect(),
+            Self::Percentile(percentile) => {
+                let mut sorted = similarities.to_vec();
+                sorted.sort_by(f32::total_cmp);
+                let count =
+                    ((percentile.clamp(0.0, 100.0) / 100.0) * sorted.len() as f32).ceil() as usize;
+                match count.checked_sub(1).map(|i| sorted[i]) {
+                    Some(cutoff) => similarities
+                        .iter()
+                        .map(|&similarity| similarity <= cutoff)
+                        .collect(),
+                    None => vec![false; similarities.len()],
+                }
+            }
+        }
+    }
+}
+
+/// Split text into sentences at `.`, `!` or `?` followed by whitespace, and at blank lines.
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        current.push(c);
+        let next = chars.peek().copied();
+        let end_of_sentence =
+            matches!(c, '.' | '!' | '?') && next.filter(|c| !c.is_whitespace()).is_none();
+        let blank_line = c == '\n' && next == Some('\n');
+        if end_of_sentence || blank_line {
+            let sentence = current.trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence.to_string());
+            }
+            current.clear();
+        }
+    }
+    let sentence = current.trim();
+    if !sentence.is_empty() {
+        sentences.push(sentence.to_string());
+    }
+    sentences
+}
+
+/// Group sentences into chunks, starting a new chunk where adjacent sentences are
+/// dissimilar or the chunk would exceed `max_tokens`.
+pub fn semantic_chunks(
+    model: &mut TextEmbedding,
+    text: &str,
+    breakpoint: Breakpoint,
+    max_tokens: usize,
+) -> Result<Vec<String>, EmbeddingError> {
+    let sentences = split_sentences(text);
+    if sentences.len() <= 1 {
+        return Ok(sentences);
+    }
+    let tokens = sentences
+        .iter()
+        .map(|sentence| token_length(model, sentence))
+        .collect::<Result<Vec<_>, _>>()?;
+    let embeddings = model
+        .embed(&sentences, None)
+        .map_err(|error| EmbeddingError::InferenceFailed(error.to_string()))?;
+    let similarities: Vec<f32> = embeddings
+        .windows(2)
+        .map(|pair| DistanceMetric::Cosine.score(&pair[0], &pair[1]))
+        .collect();
+    let boundaries = breakpoint.boundaries(&similarities);
+    Ok(group_sentences(
+        &sentences,
+        &tokens,
+        &boundaries,
+        max_tokens,
+    ))
+}
+
+/// Join sentences into chunks, breaking at each boundary between adjacent sentences
+/// and before a sentence that would take the chunk over `max_tokens`.
+fn group_sentences(
+    sentences: &[String],
+    tokens: &[usize],
+    boundaries: &[bool],
+    max_tokens: usize,
+) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current: Vec<&str> = vec![&sentences[0]];
+    let mut current_tokens = tokens[0];
+    for ((sentence, &sentence_tokens), &boundary) in
+        sentences.iter().zip(tokens).skip(1).zip(boundaries)
+    {
+        if boundary || current_tokens + sentence_tokens > max_tokens {
+            chunks.push(current.join(" "));
+            current.clear();
+            current_tokens = 0;
+        }
+        current.push(sentence);
+        current_tokens += sentence_tokens;
+    }
+    chunks.push(current.join(" "));
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedding::init_text_embedding;
+
+    fn sentences(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    #[test]
+    fn sentences_end_at_punctuation_followed_by_whitespace() {
+        assert_eq!(
+            split_sentences("One. Two!  Three? Four"),
+            vec!["One.", "Two!", "Three?", "Four"]
+        );
+        assert_eq!(
+            split_sentences("Version 1.5 is out. See example.com now."),
+            vec!["Version 1.5 is out.", "See example.com now."]
+        );
+        assert_eq!(split_sentences("Ends here."), vec!["Ends here."]);
+    }
+
+    #[test]
+    fn blank_lines_end_sentences() {
+        assert_eq!(
+            split_sentences("A heading\n\nA paragraph\nover two lines"),
+            vec!["A heading", "A paragraph\nover two lines"]
+        );
+    }
+
+    #[test]
+    fn whitespace_only_text_has_no_sentences() {
+        assert!(split_sentences("").is_empty());
+        assert!(split_sentences(" \n\n ").is_empty());
+    }
+
+    #[test]
+    fn multibyte_text_is_split_between_characters() {
+        assert_eq!(
+            split_sentences("Ça va? Très bien. 日本語!"),
+            vec!["Ça va?", "Très bien.", "日本語!"]
+        );
+    }
+
+    #[test]
+    fn thresholds_break_below_the_value() {
+        let boundaries = Breakpoint::Threshold(0.5).boundaries(&[0.9, 0.5, 0.2]);
+        assert_eq!(boundaries, vec![false, false, true]);
+    }
+
+    #[test]
+    fn percentiles_break_at_the_least_similar_gaps() {
+        let similarities = [0.9, 0.1, 0.8, 0.3];
+        assert_eq!(
+            Breakpoint::Percentile(50.0).boundaries(&similarities),
+            vec![false, true, false, true]
+        );
+        assert_eq!(
+            Breakpoint::Percentile(25.0).boundaries(&similarities),
+            vec![false, true, false, false]
+        );
+        assert_eq!(
+            Breakpoint::Percentile(0.0).boundaries(&similarities),
+            vec![false; 4]
+        );
+        assert_eq!(
+            Breakpoint::Percentile(150.0).boundaries(&similarities),
+            vec![true; 4]
+        );
+    }
+
+    #[test]
+    fn sentences_group_between_boundaries() {
+        let chunks = group_sentences(
+            &sentences(&["A.", "B.", "C.", "D."]),
+            &[1, 1, 1, 1],
+            &[false, true, false],
+            10,
+        );
+        assert_eq!(chunks, vec!["A. B.", "C. D."]);
+    }
+
+    #[test]
+    fn chunks_break_before_exceeding_max_tokens() {
+        let chunks = group_sentences(
+            &sentences(&["A.", "B.", "C.", "D."]),
+            &[3, 3, 3, 3],
+            &[false, false, false],
+            7,
+        );
+        assert_eq!(chunks, vec!["A. B.", "C. D."]);
+        // A sentence over the limit on its own still forms a chunk
+        let chunks = group_sentences(&sentences(&["A.", "B."]), &[9, 1], &[false], 7);
+        assert_eq!(chunks, vec!["A.", "B."]);
+    }
+
+    #[test]
+    #[ignore = "downloads the embedding model"]
+    fn unrelated_sentences_are_split() {
+        let mut model = init_text_embedding().unwrap();
+        let text = "The cat sat on the mat. The cat chased a mouse. \
+                    Interest rates rose again this quarter. Bond yields followed.";
+        let chunks = semantic_chunks(&mut model, text, Breakpoint::Percentile(33.0), 256).unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                "The cat sat on the mat. The cat chased a mouse.",
+                "Interest rates rose again this quarter. Bond yields followed."
+            ]
+        );
+        let chunks = semantic_chunks(&mut model, text, Breakpoint::Threshold(-1.0), 256).unwrap();
+        assert_eq!(chunks, vec![split_sentences(text).join(" ")]);
+    }
+}
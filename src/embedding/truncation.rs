@@ -0,0 +1,230 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokenizers::Tokenizer;
+
+use super::{tokenizer_length, EmbeddingError};
+
+/// What to do with a chunk longer than the model's maximum sequence length.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Truncation {
+    /// Reject the request with `input_too_long`.
+    #[default]
+    Error,
+    /// Keep the start of the chunk and drop the end.
+    TruncateEnd,
+    /// Keep the end of the chunk and drop the start.
+    TruncateStart,
+    /// Keep the start and the end of the chunk and drop the middle.
+    MiddleOut,
+    /// Split the chunk into as many extra chunks as needed, losing nothing.
+    Split,
+}
+
+/// A chunk after the policy was applied: the texts to embed in its place and
+/// whether any of its content was dropped.
+pub struct Fitted {
+    pub texts: Vec<String>,
+    pub truncated: bool,
+}
+
+impl Truncation {
+    /// Make `text` fit the model's tokenizer, leaving it unchanged when it already does.
+    pub fn fit(&self, tokenizer: &Tokenizer, text: String) -> Result<Fitted, EmbeddingError> {
+        let unchanged = |text| Fitted {
+            texts: vec![text],
+            truncated: false,
+        };
+        let Some(max) = tokenizer
+            .get_truncation()
+            .map(|truncation| truncation.max_length)
+        else {
+            return Ok(unchanged(text));
+        };
+        let tokens = tokenizer_length(tokenizer, &text)?;
+        if tokens <= max {
+            return Ok(unchanged(text));
+        }
+        if *self == Self::Error {
+            return Err(EmbeddingError::InputTooLong { tokens, max });
+        }
+        let offsets = content_offsets(tokenizer, &text)?;
+        // Special tokens such as [CLS] and [SEP] are added to every input
+        let mut budget = max.saturating_sub(tokenizer_length(tokenizer, "")?).max(1);
+        // Cutting inside a word can re-tokenize into more tokens, so shrink until it fits
+        loop {
+            let texts = self.cut(&text, &offsets, budget);
+            let longest = texts
+                .iter()
+                .map(|text| tokenizer_length(tokenizer, text))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .max()
+                .unwrap_or(0);
+            if longest <= max {
+                return Ok(Fitted {
+                    texts,
+                    truncated: *self != Self::Split,
+                });
+            }
+            // Not even a single content token fits alongside the special tokens
+            if budget == 1 {
+                return Err(EmbeddingError::InputTooLong { tokens, max });
+            }
+            budget = budget.saturating_sub(longest - max).max(1);
+        }
+    }
+
+    /// Cut `text` to `budget` content tokens, or into pieces of `budget` tokens when splitting.
+    fn cut(&self, text: &str, offsets: &[(usize, usize)], budget: usize) -> Vec<String> {
+        let span = |tokens: &[(usize, usize)]| match (tokens.first(), tokens.last()) {
+            (Some(&(start, _)), Some(&(_, end))) => text[start..end].to_string(),
+            _ => String::new(),
+        };
+        let keep = budget.min(offsets.len());
+        match self {
+            Self::Error | Self::TruncateEnd => vec![text[..span_end(&offsets[..keep])].to_string()],
+            Self::TruncateStart => {
+                let start = offsets[offsets.len() - keep..]
+                    .first()
+                    .map_or(text.len(), |&(start, _)| start);
+                vec![text[start..].to_string()]
+            }
+            Self::MiddleOut => {
+                let head = keep.div_ceil(2);
+                let tail = keep - head;
+                let head = &text[..span_end(&offsets[..head])];
+                let tail = span(&offsets[offsets.len() - tail..]);
+                vec![format!("{head} {tail}").trim_end().to_string()]
+            }
+            Self::Split => offsets.chunks(budget).map(span).collect(),
+        }
+    }
+}
+
+fn span_end(tokens: &[(usize, usize)]) -> usize {
+    tokens.last().map_or(0, |&(_, end)| end)
+}
+
+/// Byte ranges of every token of `text` other than special tokens, including
+/// tokens the tokenizer's own truncation moves into overflowing encodings.
+fn content_offsets(
+    tokenizer: &Tokenizer,
+    text: &str,
+) -> Result<Vec<(usize, usize)>, EmbeddingError> {
+    let encoding = tokenizer
+        .encode(text, true)
+        .map_err(|error| EmbeddingError::TokenizationFailed(error.to_string()))?;
+    Ok(std::iter::once(&encoding)
+        .chain(encoding.get_overflowing())
+        .flat_map(|encoding| {
+            encoding
+                .get_offsets()
+                .iter()
+                .zip(encoding.get_special_tokens_mask())
+                .filter(|(_, &special)| special == 0)
+                .map(|(&offset, _)| offset)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedding::tests::tokenizer;
+
+    /// Seven tokens with special tokens, over a limit of four that leaves two for content.
+    const LONG: &str = "é b 日本 a é";
+
+    fn fit(truncation: Truncation, text: &str) -> Fitted {
+        truncation.fit(&tokenizer(4), text.to_string()).unwrap()
+    }
+
+    #[test]
+    fn short_text_is_unchanged_by_every_policy() {
+        for truncation in [
+            Truncation::Error,
+            Truncation::TruncateEnd,
+            Truncation::TruncateStart,
+            Truncation::MiddleOut,
+            Truncation::Split,
+        ] {
+            let fitted = fit(truncation, "日本 é");
+            assert_eq!(fitted.texts, vec!["日本 é"], "{truncation:?}");
+            assert!(!fitted.truncated, "{truncation:?}");
+        }
+    }
+
+    #[test]
+    fn error_rejects_long_text() {
+        let result = Truncation::Error.fit(&tokenizer(4), LONG.to_string());
+        assert!(matches!(
+            result,
+            Err(EmbeddingError::InputTooLong { tokens: 7, max: 4 })
+        ));
+    }
+
+    #[test]
+    fn truncate_end_keeps_the_start() {
+        let fitted = fit(Truncation::TruncateEnd, LONG);
+        assert_eq!(fitted.texts, vec!["é b"]);
+        assert!(fitted.truncated);
+    }
+
+    #[test]
+    fn truncate_start_keeps_the_end() {
+        let fitted = fit(Truncation::TruncateStart, LONG);
+        assert_eq!(fitted.texts, vec!["a é"]);
+        assert!(fitted.truncated);
+    }
+
+    #[test]
+    fn middle_out_keeps_both_ends() {
+        let fitted = fit(Truncation::MiddleOut, LONG);
+        assert_eq!(fitted.texts, vec!["é é"]);
+        assert!(fitted.truncated);
+    }
+
+    #[test]
+    fn split_keeps_everything() {
+        let fitted = fit(Truncation::Split, LONG);
+        assert_eq!(fitted.texts, vec!["é b", "日本 a", "é"]);
+        assert!(!fitted.truncated);
+    }
+
+    #[test]
+    fn multibyte_tokens_are_cut_at_their_boundaries() {
+        let fitted = fit(Truncation::TruncateEnd, "日本 日本 日本");
+        assert_eq!(fitted.texts, vec!["日本 日本"]);
+        let fitted = fit(Truncation::TruncateStart, "日本 日本 é");
+        assert_eq!(fitted.texts, vec!["日本 é"]);
+    }
+
+    #[test]
+    fn limits_without_room_for_content_reject_long_text() {
+        // A limit of two leaves no room beside [CLS] and [SEP]
+        for truncation in [
+            Truncation::TruncateEnd,
+            Truncation::TruncateStart,
+            Truncation::MiddleOut,
+            Truncation::Split,
+        ] {
+            let result = truncation.fit(&tokenizer(2), "a b".to_string());
+            assert!(
+                matches!(
+                    result,
+                    Err(EmbeddingError::InputTooLong { tokens: 4, max: 2 })
+                ),
+                "{truncation:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn tokenizers_without_a_limit_keep_everything() {
+        let mut tokenizer = tokenizer(4);
+        tokenizer.with_truncation(None).unwrap();
+        let fitted = Truncation::Error.fit(&tokenizer, LONG.to_string()).unwrap();
+        assert_eq!(fitted.texts, vec![LONG]);
+    }
+}
@@ -0,0 +1,790 @@
+pub mod routes;
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+use schemars::JsonSchema;
+use scraper::{ElementRef, Html, Node};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    embedding::{LocalOrRemoteFile, LocalOrRemoteFileReadError},
+    server::config::{env_opt, env_or},
+};
+
+const DEFAULT_MAX_CHUNK_CHARS: usize = 1000;
+const DEFAULT_MAX_REMOTE_BYTES: usize = 10 * 1024 * 1024;
+const DEFAULT_REMOTE_TIMEOUT_SECS: u64 = 30;
+const SECTION_SEPARATOR: &str = " > ";
+const SKIPPED_HTML_ELEMENTS: [&str; 7] = [
+    "script", "style", "noscript", "template", "nav", "head", "svg",
+];
+const BLOCK_HTML_ELEMENTS: [&str; 16] = [
+    "p",
+    "div",
+    "li",
+    "pre",
+    "blockquote",
+    "tr",
+    "td",
+    "th",
+    "br",
+    "section",
+    "article",
+    "header",
+    "footer",
+    "main",
+    "dd",
+    "dt",
+];
+
+pub fn default_max_chunk_chars() -> usize {
+    DEFAULT_MAX_CHUNK_CHARS
+}
+
+#[derive(Clone, Debug)]
+pub struct IngestConfig {
+    /// Directory local files may be read from. Local files are refused when unset.
+    pub local_dir: Option<PathBuf>,
+    /// Hosts remote files may be fetched from. Remote files are refused when empty.
+    pub remote_hosts: Vec<String>,
+    /// Maximum size of a remote file.
+    pub max_remote_bytes: usize,
+    /// Time allowed to fetch a remote file, from connecting to the end of the body.
+    pub remote_timeout: Duration,
+}
+
+impl Default for IngestConfig {
+    fn default() -> Self {
+        Self {
+            local_dir: None,
+            remote_hosts: Vec::new(),
+            max_remote_bytes: DEFAULT_MAX_REMOTE_BYTES,
+            remote_timeout: Duration::from_secs(DEFAULT_REMOTE_TIMEOUT_SECS),
+        }
+    }
+}
+
+impl IngestConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            local_dir: env_opt("FASTEMBED_INGEST_DIR"),
+            remote_hosts: env_or("FASTEMBED_INGEST_REMOTE_HOSTS", String::new())
+                .split(',')
+                .map(|host| host.trim().to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            max_remote_bytes: env_or(
+                "FASTEMBED_INGEST_MAX_REMOTE_BYTES",
+                default.max_remote_bytes,
+            ),
+            remote_timeout: Duration::from_secs(env_or(
+                "FASTEMBED_INGEST_REMOTE_TIMEOUT_SECS",
+                DEFAULT_REMOTE_TIMEOUT_SECS,
+            )),
+        }
+    }
+
+    /// Read a file, only allowing local paths inside `local_dir` and remote files
+    /// on `remote_hosts` that resolve to public addresses.
+    pub async fn read(&self, file: LocalOrRemoteFile) -> Result<Vec<u8>, IngestError> {
+        match file {
+            LocalOrRemoteFile::Local(path) => {
+                self.check_local(&path)?;
+                LocalOrRemoteFile::Local(path)
+                    .async_read_local_or_remote_file_to_bytes()
+                    .await
+                    .map_err(IngestError::Read)
+            }
+            LocalOrRemoteFile::Remote(url) => self.fetch(&url).await,
+        }
+    }
+
+    /// Fetch a remote file from an allowed host, connecting only to the addresses
+    /// checked here so DNS can't be rebound to an internal one, and never following
+    /// redirects.
+    async fn fetch(&self, url: &str) -> Result<Vec<u8>, IngestError> {
+        let (host, addresses) = self.resolve_remote(url).await?;
+        let mut client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(self.remote_timeout);
+        for address in addresses {
+            client = client.resolve(&host, address);
+        }
+        let client = client.build().map_err(remote_error)?;
+        let mut response = client.get(url).send().await.map_err(remote_error)?;
+        if !response.status().is_success() {
+            return Err(IngestError::RemoteStatus(response.status().as_u16()));
+        }
+        read_capped(&mut response, self.max_remote_bytes).await
+    }
+
+    /// Check the URL's host against `remote_hosts` and resolve it, refusing hosts
+    /// with any address that isn't public.
+    async fn resolve_remote(&self, url: &str) -> Result<(String, Vec<SocketAddr>), IngestError> {
+        let url =
+            reqwest::Url::parse(url).map_err(|error| IngestError::InvalidUrl(error.to_string()))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(IngestError::InvalidUrl(format!(
+                "unsupported scheme {}",
+                url.scheme()
+            )));
+        }
+        if self.remote_hosts.is_empty() {
+            return Err(IngestError::RemoteFilesDisabled);
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| IngestError::InvalidUrl("the URL has no host".to_string()))?
+            .to_ascii_lowercase();
+        if !self.remote_hosts.contains(&host) {
+            return Err(IngestError::RemoteHostNotAllowed(host));
+        }
+        let port = url.port_or_known_default().unwrap_or(443);
+        let lookup = host.trim_start_matches('[').trim_end_matches(']');
+        let addresses: Vec<_> = tokio::net::lookup_host((lookup, port))
+            .await
+            .map_err(|error| IngestError::Read(LocalOrRemoteFileReadError::Local(error)))?
+            .collect();
+        if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+            return Err(IngestError::PrivateAddress(address.ip()));
+        }
+        Ok((host, addresses))
+    }
+
+    fn check_local(&self, path: &Path) -> Result<(), IngestError> {
+        let root = self
+            .local_dir
+            .as_ref()
+            .ok_or(IngestError::LocalFilesDisabled)?
+            .canonicalize()
+            .map_err(|error| IngestError::Read(LocalOrRemoteFileReadError::Local(error)))?;
+        let path = path
+            .canonicalize()
+            .map_err(|error| IngestError::Read(LocalOrRemoteFileReadError::Local(error)))?;
+        if path.starts_with(root) {
+            Ok(())
+        } else {
+            Err(IngestError::OutsideIngestDir)
+        }
+    }
+}
+
+fn remote_error(error: reqwest::Error) -> IngestError {
+    IngestError::Read(LocalOrRemoteFileReadError::Remote(error))
+}
+
+/// Read a response body, giving up as soon as it exceeds `max_bytes`.
+async fn read_capped(
+    response: &mut reqwest::Response,
+    max_bytes: usize,
+) -> Result<Vec<u8>, IngestError> {
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes as u64)
+    {
+        return Err(IngestError::TooLarge(max_bytes));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(remote_error)? {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(IngestError::TooLarge(max_bytes));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Whether an address is reachable on the public internet, so not loopback,
+/// private, link-local, shared or otherwise reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // Shared address space, 100.64.0.0/10
+                || (a == 100 && (b & 0b1100_0000) == 64)
+                // "This network" and reserved ranges
+                || a == 0
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local, fc00::/7
+                || (first & 0xfe00) == 0xfc00
+                // Link-local, fe80::/10
+                || (first & 0xffc0) == 0xfe80
+                // Documentation, 2001:db8::/32
+                || (first == 0x2001 && ip.segments()[1] == 0xdb8))
+        }
+    }
+}
+
+/// Formats text can be extracted from.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentFormat {
+    Html,
+    Markdown,
+    Pdf,
+    /// Plain text, embedded as a single section.
+    Text,
+}
+
+impl DocumentFormat {
+    /// Guess the format from a file name or URL extension.
+    pub fn from_extension(name: &str) -> Option<Self> {
+        let name = name.split(['?', '#']).next().unwrap_or(name);
+        let extension = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "html" | "htm" | "xhtml" => Some(Self::Html),
+            "md" | "markdown" => Some(Self::Markdown),
+            "pdf" => Some(Self::Pdf),
+            "txt" => Some(Self::Text),
+            _ => None,
+        }
+    }
+}
+
+/// Text under one heading, or one page of a PDF.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    /// Heading path, e.g. "Install > Linux", `None` before the first heading.
+    pub title: Option<String>,
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub enum IngestError {
+    UnknownFormat,
+    LocalFilesDisabled,
+    OutsideIngestDir,
+    RemoteFilesDisabled,
+    RemoteHostNotAllowed(String),
+    /// The remote host resolved to an address that isn't public.
+    PrivateAddress(IpAddr),
+    InvalidUrl(String),
+    RemoteStatus(u16),
+    /// The remote file exceeds this many bytes.
+    TooLarge(usize),
+    Read(LocalOrRemoteFileReadError),
+    InvalidUtf8,
+    Pdf(String),
+}
+
+impl std::fmt::Display for IngestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(
+                f,
+                "The document format could not be inferred, set format to html, markdown, pdf or text"
+            ),
+            Self::LocalFilesDisabled => write!(
+                f,
+                "Local files are disabled, set FASTEMBED_INGEST_DIR to allow them"
+            ),
+            Self::OutsideIngestDir => write!(f, "Local files must be inside the ingest directory"),
+            Self::RemoteFilesDisabled => write!(
+                f,
+                "Remote files are disabled, set FASTEMBED_INGEST_REMOTE_HOSTS to allow hosts"
+            ),
+            Self::RemoteHostNotAllowed(host) => write!(
+                f,
+                "The host {host} is not in FASTEMBED_INGEST_REMOTE_HOSTS"
+            ),
+            Self::PrivateAddress(ip) => write!(
+                f,
+                "The host resolved to {ip}, remote files must be on public addresses"
+            ),
+            Self::InvalidUrl(error) => write!(f, "Invalid URL: {error}"),
+            Self::RemoteStatus(status) => write!(f, "The remote server responded with {status}"),
+            Self::TooLarge(limit) => write!(f, "The document exceeds the limit of {limit} bytes"),
+            Self::Read(error) => write!(f, "Failed to read the document: {error}"),
+            Self::InvalidUtf8 => write!(f, "The document is not valid UTF-8"),
+            Self::Pdf(error) => write!(f, "Failed to extract text from the PDF: {error}"),
+        }
+    }
+}
+
+/// Extract the text of a document as sections, keeping its heading structure.
+pub fn extract_sections(format: DocumentFormat, bytes: &[u8]) -> Result<Vec<Section>, IngestError> {
+    let sections = match format {
+        DocumentFormat::Pdf => pdf_sections(bytes)?,
+        format => {
+            let text = std::str::from_utf8(bytes).map_err(|_| IngestError::InvalidUtf8)?;
+            match format {
+                DocumentFormat::Html => html_sections(text),
+                DocumentFormat::Markdown => markdown_sections(text),
+                _ => vec![Section {
+                    title: None,
+                    text: text.to_string(),
+                }],
+            }
+        }
+    };
+    Ok(sections
+        .into_iter()
+        .map(|section| Section {
+            title: section.title,
+            text: tidy(&section.text),
+        })
+        .filter(|section| !section.text.is_empty())
+        .collect())
+}
+
+/// Collapse spaces within lines and blank lines between paragraphs.
+fn tidy(text: &str) -> String {
+    text.split("\n\n")
+        .map(|paragraph| paragraph.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Builds sections while tracking the current heading path.
+#[derive(Default)]
+struct SectionBuilder {
+    headings: Vec<(usize, String)>,
+    text: String,
+    sections: Vec<Section>,
+}
+
+impl SectionBuilder {
+    fn title(&self) -> Option<String> {
+        (!self.headings.is_empty()).then(|| {
+            self.headings
+                .iter()
+                .map(|(_, heading)| heading.as_str())
+                .collect::<Vec<_>>()
+                .join(SECTION_SEPARATOR)
+        })
+    }
+
+    fn flush(&mut self) {
+        let text = std::mem::take(&mut self.text);
+        if !text.trim().is_empty() {
+            self.sections.push(Section {
+                title: self.title(),
+                text,
+            });
+        }
+    }
+
+    fn heading(&mut self, level: usize, heading: String) {
+        self.flush();
+        self.headings.retain(|(parent, _)| *parent < level);
+        self.headings.push((level, heading.trim().to_string()));
+    }
+
+    fn push(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    fn paragraph(&mut self) {
+        self.text.push_str("\n\n");
+    }
+
+    fn finish(mut self) -> Vec<Section> {
+        self.flush();
+        self.sections
+    }
+}
+
+fn markdown_sections(markdown: &str) -> Vec<Section> {
+    let mut builder = SectionBuilder::default();
+    let mut heading: Option<(usize, String)> = None;
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                heading = Some((heading_level(level), String::new()));
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((level, text)) = heading.take() {
+                    builder.heading(level, text);
+                }
+            }
+            Event::Text(text) | Event::Code(text) => match &mut heading {
+                Some((_, heading)) => heading.push_str(&text),
+                None => builder.push(&text),
+            },
+            Event::SoftBreak | Event::HardBreak => builder.push(" "),
+            Event::End(TagEnd::Paragraph | TagEnd::Item | TagEnd::CodeBlock | TagEnd::TableRow) => {
+                builder.paragraph()
+            }
+            Event::End(TagEnd::TableCell) => builder.push(" "),
+            _ => {}
+        }
+    }
+    builder.finish()
+}
+
+fn heading_level(level: HeadingLevel) -> usize {
+    match level {
+        HeadingLevel::H1 => 1,
+        HeadingLevel::H2 => 2,
+        HeadingLevel::H3 => 3,
+        HeadingLevel::H4 => 4,
+        HeadingLevel::H5 => 5,
+        HeadingLevel::H6 => 6,
+    }
+}
+
+fn html_sections(html: &str) -> Vec<Section> {
+    let document = Html::parse_document(html);
+    let mut builder = SectionBuilder::default();
+    walk_html(document.root_element(), &mut builder);
+    builder.finish()
+}
+
+fn walk_html(element: ElementRef, builder: &mut SectionBuilder) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => builder.push(text),
+            Node::Element(_) => {
+                let Some(child) = ElementRef::wrap(child) else {
+                    continue;
+                };
+                let name = child.value().name();
+                if SKIPPED_HTML_ELEMENTS.contains(&name) {
+                    continue;
+                }
+                if let Some(level) = html_heading_level(name) {
+                    builder.heading(level, child.text().collect::<String>());
+                    continue;
+                }
+                walk_html(child, builder);
+                if BLOCK_HTML_ELEMENTS.contains(&name) {
+                    builder.paragraph();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn html_heading_level(name: &str) -> Option<usize> {
+    match name {
+        "h1" => Some(1),
+        "h2" => Some(2),
+        "h3" => Some(3),
+        "h4" => Some(4),
+        "h5" => Some(5),
+        "h6" => Some(6),
+        _ => None,
+    }
+}
+
+fn pdf_sections(bytes: &[u8]) -> Result<Vec<Section>, IngestError> {
+    // pdf-extract panics on some malformed documents
+    let pages = std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem_by_pages(bytes))
+        .map_err(|_| IngestError::Pdf("the document is malformed".to_string()))?
+        .map_err(|error| IngestError::Pdf(error.to_string()))?;
+    Ok(pages
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| Section {
+            title: Some(format!("Page {}", i + 1)),
+            // PDF text has hard line breaks within paragraphs
+            text: text
+                .split("\n\n")
+                .map(|paragraph| paragraph.replace('\n', " "))
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        })
+        .collect())
+}
+
+/// Split a section into chunks of at most `max_chars` characters, breaking
+/// between paragraphs where possible.
+pub fn chunk_section(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for paragraph in text.split("\n\n") {
+        let paragraph_chars = paragraph.chars().count();
+        if paragraph_chars > max_chars {
+            if !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+            }
+            let chars: Vec<char> = paragraph.chars().collect();
+            chunks.extend(chars.chunks(max_chars).map(|chunk| chunk.iter().collect()));
+            continue;
+        }
+        let separator = if current.is_empty() { 0 } else { 2 };
+        if current.chars().count() + separator + paragraph_chars > max_chars {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(paragraph);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use axum::{routing::get, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn allowing(hosts: &[&str]) -> IngestConfig {
+        IngestConfig {
+            remote_hosts: hosts.iter().map(|host| host.to_string()).collect(),
+            ..IngestConfig::default()
+        }
+    }
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        for ip in [
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3)),
+            IpAddr::V4(Ipv4Addr::new(172, 16, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+            IpAddr::V4(Ipv4Addr::new(169, 254, 169, 254)),
+            IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1)),
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+            IpAddr::V6("fd00::1".parse().unwrap()),
+            IpAddr::V6("fe80::1".parse().unwrap()),
+            IpAddr::V6("::ffff:127.0.0.1".parse().unwrap()),
+        ] {
+            assert!(!is_public(ip), "{ip}");
+        }
+        for ip in [
+            IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)),
+            IpAddr::V4(Ipv4Addr::new(100, 128, 0, 1)),
+            IpAddr::V6("2606:4700::1111".parse().unwrap()),
+        ] {
+            assert!(is_public(ip), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn remote_files_are_opt_in() {
+        let result = IngestConfig::default()
+            .read(LocalOrRemoteFile::Remote(
+                "https://example.com/a.html".to_string(),
+            ))
+            .await;
+        assert!(matches!(result, Err(IngestError::RemoteFilesDisabled)));
+    }
+
+    #[tokio::test]
+    async fn only_allowed_hosts_are_fetched() {
+        let config = allowing(&["docs.example.com"]);
+        let result = config.resolve_remote("https://example.com/a.html").await;
+        assert!(
+            matches!(result, Err(IngestError::RemoteHostNotAllowed(host)) if host == "example.com")
+        );
+        let result = config.resolve_remote("file:///etc/passwd").await;
+        assert!(matches!(result, Err(IngestError::InvalidUrl(_))));
+    }
+
+    #[tokio::test]
+    async fn allowed_hosts_on_private_addresses_are_refused() {
+        let config = allowing(&["169.254.169.254", "127.0.0.1", "[::1]"]);
+        for url in [
+            "http://169.254.169.254/latest/meta-data/",
+            "http://127.0.0.1:3100/admin",
+            "http://[::1]/",
+        ] {
+            let result = config.resolve_remote(url).await;
+            assert!(
+                matches!(result, Err(IngestError::PrivateAddress(_))),
+                "{url}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn bodies_over_the_cap_are_refused() {
+        let app = Router::new()
+            .route("/small", get(|| async { "x".repeat(8) }))
+            .route("/large", get(|| async { "x".repeat(64) }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let mut small = reqwest::get(format!("http://{address}/small"))
+            .await
+            .unwrap();
+        assert_eq!(read_capped(&mut small, 16).await.unwrap(), b"xxxxxxxx");
+        let mut large = reqwest::get(format!("http://{address}/large"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            read_capped(&mut large, 16).await,
+            Err(IngestError::TooLarge(16))
+        ));
+    }
+
+    fn section(title: Option<&str>, text: &str) -> Section {
+        Section {
+            title: title.map(str::to_string),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn markdown_sections_follow_the_heading_path() {
+        let markdown = "Intro text.\n\n# Install\n\nGet it.\n\n## Linux\n\nUse apt.\n\nOr   snap.\n\n## macOS\n\nUse brew.\n\n# Usage\n\nRun `serve`.\n";
+        let sections = extract_sections(DocumentFormat::Markdown, markdown.as_bytes()).unwrap();
+        assert_eq!(
+            sections,
+            vec![
+                section(None, "Intro text."),
+                section(Some("Install"), "Get it."),
+                section(Some("Install > Linux"), "Use apt.\n\nOr snap."),
+                section(Some("Install > macOS"), "Use brew."),
+                section(Some("Usage"), "Run serve."),
+            ]
+        );
+    }
+
+    #[test]
+    fn html_sections_follow_the_heading_path() {
+        let html = "<html><head><title>Ignored</title><style>p { color: red }</style></head>\
+            <body><h1>Install</h1><p>Get it.</p><h3>Linux</h3><p>Use apt.</p><p>Or snap.</p>\
+            <script>alert(1)</script><h2>macOS</h2><div>Use <b>brew</b>.</div></body></html>";
+        let sections = extract_sections(DocumentFormat::Html, html.as_bytes()).unwrap();
+        assert_eq!(
+            sections,
+            vec![
+                section(Some("Install"), "Get it."),
+                section(Some("Install > Linux"), "Use apt.\n\nOr snap."),
+                section(Some("Install > macOS"), "Use brew."),
+            ]
+        );
+    }
+
+    #[test]
+    fn text_is_a_single_untitled_section() {
+        let sections =
+            extract_sections(DocumentFormat::Text, b"First  line\nsecond.\n\n\n\nNext.").unwrap();
+        assert_eq!(sections, vec![section(None, "First line second.\n\nNext.")]);
+        assert!(extract_sections(DocumentFormat::Text, b"  \n\n ")
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            extract_sections(DocumentFormat::Text, &[0xff, 0xfe]),
+            Err(IngestError::InvalidUtf8)
+        ));
+    }
+
+    #[test]
+    fn chunks_break_between_paragraphs() {
+        let text = "aaaa\n\nbbbb\n\ncccc";
+        assert_eq!(chunk_section(text, 100), vec![text]);
+        assert_eq!(chunk_section(text, 10), vec!["aaaa\n\nbbbb", "cccc"]);
+        assert_eq!(chunk_section(text, 4), vec!["aaaa", "bbbb", "cccc"]);
+    }
+
+    #[test]
+    fn long_paragraphs_are_split_by_characters() {
+        assert_eq!(
+            chunk_section("ab\n\nééééé\n\ncd", 2),
+            vec!["ab", "éé", "éé", "é", "cd"]
+        );
+        assert_eq!(chunk_section("abc", 0), vec!["a", "b", "c"]);
+    }
+
+    /// A minimal PDF with one page per entry, each line drawn on its own row.
+    fn pdf(pages: &[&[&str]]) -> Vec<u8> {
+        let first_page = 4;
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                (0..pages.len())
+                    .map(|i| format!("{} 0 R", first_page + 2 * i))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                pages.len()
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+        ];
+        for (i, lines) in pages.iter().enumerate() {
+            let text: String = lines
+                .iter()
+                .map(|line| format!("({line}) Tj 0 -14 Td "))
+                .collect();
+            let stream = format!("BT /F1 12 Tf 72 720 Td {text}ET");
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] \
+                 /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+                first_page + 2 * i + 1
+            ));
+            objects.push(format!(
+                "<< /Length {} >>\nstream\n{stream}\nendstream",
+                stream.len()
+            ));
+        }
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend(format!("{} 0 obj\n{object}\nendobj\n", i + 1).bytes());
+        }
+        let xref = pdf.len();
+        pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).bytes());
+        for offset in offsets {
+            pdf.extend(format!("{offset:010} 00000 n \n").bytes());
+        }
+        pdf.extend(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+                objects.len() + 1
+            )
+            .bytes(),
+        );
+        pdf
+    }
+
+    #[test]
+    fn pdf_pages_are_sections() {
+        let sections = extract_sections(
+            DocumentFormat::Pdf,
+            &pdf(&[&["Hello", "world."], &[], &["Last page."]]),
+        )
+        .unwrap();
+        let titles: Vec<_> = sections
+            .iter()
+            .map(|section| section.title.as_deref())
+            .collect();
+        assert_eq!(titles, vec![Some("Page 1"), Some("Page 3")]);
+        assert_eq!(sections[0].text, "Hello world.");
+        assert_eq!(sections[1].text, "Last page.");
+    }
+
+    #[test]
+    fn malformed_pdfs_are_errors() {
+        for bytes in [&b"not a pdf"[..], b"%PDF-1.4\n1 0 obj\n<< /Type /Catalog"] {
+            let result = extract_sections(DocumentFormat::Pdf, bytes);
+            assert!(matches!(result, Err(IngestError::Pdf(_))));
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use aide::axum::{routing::post_with, ApiRouter};
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+use super::{
+    chunk_section, default_max_chunk_chars, extract_sections, DocumentFormat, IngestError,
+};
+use crate::{
+    embedding::{
+        embed_with_state, DocumentId, EmbedSettings, EmbeddingRequestUnit, EmbeddingResponse,
+        LocalOrRemoteFile, Metadata,
+    },
+    server::{
+        errors::AppError,
+        extractors::{ClientId, Json},
+        limits::Fields,
+        state::AppState,
+    },
+};
+
+pub fn ingest_routes(state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .api_route(
+            "/file",
+            post_with(ingest_file, |op| {
+                op.description(
+                    "Extract text from a local or remote HTML, Markdown or PDF file and embed it section by section.",
+                )
+            }),
+        )
+        .api_route(
+            "/upload",
+            post_with(ingest_upload, |op| {
+                op.description(
+                    "Extract text from an uploaded HTML, Markdown or PDF body and embed it section by section.",
+                )
+            }),
+        )
+        .with_state(state)
+}
+
+impl From<IngestError> for AppError {
+    fn from(error: IngestError) -> Self {
+        let status = match error {
+            IngestError::LocalFilesDisabled
+            | IngestError::OutsideIngestDir
+            | IngestError::RemoteFilesDisabled
+            | IngestError::RemoteHostNotAllowed(_)
+            | IngestError::PrivateAddress(_) => StatusCode::FORBIDDEN,
+            IngestError::Read(_) | IngestError::RemoteStatus(_) => StatusCode::BAD_GATEWAY,
+            IngestError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            IngestError::UnknownFormat
+            | IngestError::InvalidUrl(_)
+            | IngestError::InvalidUtf8
+            | IngestError::Pdf(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        };
+        AppError::new(&error.to_string()).with_status(status)
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct IngestFileRequest {
+    id: DocumentId,
+    file: LocalOrRemoteFile,
+    /// Inferred from the file extension when omitted.
+    format: Option<DocumentFormat>,
+    /// Maximum characters per chunk, sections are split between paragraphs, defaults to 1000.
+    #[serde(default = "default_max_chunk_chars")]
+    max_chunk_chars: usize,
+    /// Echoed on every chunk alongside the section metadata.
+    metadata: Option<Metadata>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct IngestUploadQuery {
+    /// Read as an integer when it is one, e.g. `42` but not `042`.
+    #[serde(deserialize_with = "query_document_id")]
+    id: DocumentId,
+    format: DocumentFormat,
+    /// Maximum characters per chunk, sections are split between paragraphs, defaults to 1000.
+    #[serde(default = "default_max_chunk_chars")]
+    max_chunk_chars: usize,
+}
+
+/// Query strings carry every value as text, so integers are parsed out by hand.
+fn query_document_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DocumentId, D::Error> {
+    let id = String::deserialize(deserializer)?;
+    Ok(match id.parse::<i64>() {
+        Ok(int) if int.to_string() == id => DocumentId::Int(int),
+        _ => DocumentId::String(id),
+    })
+}
+
+pub async fn ingest_file(
+    State(state): State<AppState>,
+    client: ClientId,
+    Json(payload): Json<IngestFileRequest>,
+) -> Result<(StatusCode, Json<EmbeddingResponse>), AppError> {
+    let format = match payload.format {
+        Some(format) => format,
+        None => {
+            let name = match &payload.file {
+                LocalOrRemoteFile::Local(path) => path.to_string_lossy().into_owned(),
+                LocalOrRemoteFile::Remote(url) => url.clone(),
+            };
+            DocumentFormat::from_extension(&name).ok_or(IngestError::UnknownFormat)?
+        }
+    };
+    let bytes = state.ingest.read(payload.file).await?;
+    let data = chunk_document(
+        payload.id,
+        format,
+        bytes.into(),
+        payload.max_chunk_chars,
+        payload.metadata,
+    )
+    .await?;
+    let embeddings = embed_chunks(&state, &client, data, "file").await?;
+    Ok((StatusCode::ACCEPTED, Json(embeddings)))
+}
+
+pub async fn ingest_upload(
+    State(state): State<AppState>,
+    client: ClientId,
+    Query(query): Query<IngestUploadQuery>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<EmbeddingResponse>), AppError> {
+    let data = chunk_document(query.id, query.format, body, query.max_chunk_chars, None).await?;
+    let embeddings = embed_chunks(&state, &client, data, "body").await?;
+    Ok((StatusCode::ACCEPTED, Json(embeddings)))
+}
+
+/// Extract sections and split them into chunks, with the document id, section title
+/// and chunk text attached as metadata.
+///
+/// Extraction runs on the blocking pool, untrusted PDFs can take a while to parse.
+async fn chunk_document(
+    id: DocumentId,
+    format: DocumentFormat,
+    bytes: Bytes,
+    max_chunk_chars: usize,
+    metadata: Option<Metadata>,
+) -> Result<Vec<EmbeddingRequestUnit>, AppError> {
+    let sections = tokio::task::spawn_blocking(move || extract_sections(format, &bytes))
+        .await
+        .map_err(|error| {
+            AppError::new(&format!("failed to extract the document: {error}"))
+                .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+        })??;
+    let mut data = Vec::new();
+    for (section_index, section) in sections.iter().enumerate() {
+        for (chunk_index, text) in chunk_section(&section.text, max_chunk_chars)
+            .into_iter()
+            .enumerate()
+        {
+            let mut chunk_metadata = metadata.clone().unwrap_or_default();
+            chunk_metadata.insert(
+                "document_id".to_string(),
+                serde_json::to_value(&id).unwrap_or_default(),
+            );
+            chunk_metadata.insert(
+                "section".to_string(),
+                section.title.clone().map_or(Value::Null, Value::String),
+            );
+            chunk_metadata.insert("section_index".to_string(), section_index.into());
+            chunk_metadata.insert("chunk_index".to_string(), chunk_index.into());
+            data.push(EmbeddingRequestUnit {
+                id: DocumentId::String(format!("{id}#{}", data.len())),
+                text_to_embed: text,
+                metadata: Some(chunk_metadata),
+            });
+        }
+    }
+    Ok(data)
+}
+
+/// Embed each chunk whole, naming `field` as the source of every chunk in limit violations.
+async fn embed_chunks(
+    state: &AppState,
+    client: &ClientId,
+    data: Vec<EmbeddingRequestUnit>,
+    field: &'static str,
+) -> Result<EmbeddingResponse, AppError> {
+    embed_with_state(
+        state,
+        client,
+        data,
+        EmbedSettings::new(Fields::Single(field)),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Uri;
+
+    use super::*;
+
+    fn upload_id(query: &str) -> DocumentId {
+        let uri: Uri = format!("/ingest/upload?format=pdf&{query}")
+            .parse()
+            .unwrap();
+        Query::<IngestUploadQuery>::try_from_uri(&uri).unwrap().0.id
+    }
+
+    #[tokio::test]
+    async fn chunks_are_indexed_within_their_sections() {
+        let markdown = "# Install\n\naaaa\n\nbbbb\n\n## Linux\n\ncccc\n";
+        let data = chunk_document(
+            DocumentId::Int(7),
+            DocumentFormat::Markdown,
+            Bytes::from(markdown),
+            4,
+            None,
+        )
+        .await
+        .unwrap();
+        let chunks: Vec<_> = data
+            .iter()
+            .map(|unit| {
+                let metadata = unit.metadata.as_ref().unwrap();
+                (
+                    unit.id.to_string(),
+                    unit.text_to_embed.as_str(),
+                    metadata["document_id"].clone(),
+                    metadata["section"].clone(),
+                    metadata["section_index"].clone(),
+                    metadata["chunk_index"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![
+                (
+                    "7#0".to_string(),
+                    "aaaa",
+                    7.into(),
+                    "Install".into(),
+                    0.into(),
+                    0.into()
+                ),
+                (
+                    "7#1".to_string(),
+                    "bbbb",
+                    7.into(),
+                    "Install".into(),
+                    0.into(),
+                    1.into()
+                ),
+                (
+                    "7#2".to_string(),
+                    "cccc",
+                    7.into(),
+                    "Install > Linux".into(),
+                    1.into(),
+                    0.into()
+                ),
+            ]
+        );
+        // The chunk text is already the embedded text, not copied into metadata
+        assert!(data
+            .iter()
+            .all(|unit| !unit.metadata.as_ref().unwrap().contains_key("text")));
+    }
+
+    #[test]
+    fn upload_ids_are_integers_when_they_are_one() {
+        assert_eq!(upload_id("id=42"), DocumentId::Int(42));
+        assert_eq!(upload_id("id=-7"), DocumentId::Int(-7));
+        assert_eq!(upload_id("id=042"), DocumentId::String("042".to_string()));
+        assert_eq!(
+            upload_id("id=docs%2Freadme.md"),
+            DocumentId::String("docs/readme.md".to_string())
+        );
+    }
+}
@@ -1,2 +1,6 @@
+pub mod analysis;
+pub mod client;
+pub mod collections;
 pub mod embedding;
+pub mod ingest;
 pub mod server;
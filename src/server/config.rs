@@ -0,0 +1,13 @@
+use std::str::FromStr;
+
+/// Read an environment variable and parse it, returning `None` if it is unset or invalid.
+pub fn env_opt<T: FromStr>(name: &str) -> Option<T> {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+}
+
+/// Read an environment variable and parse it, falling back to `default` if it is unset or invalid.
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env_opt(name).unwrap_or(default)
+}
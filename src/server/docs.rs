@@ -71,6 +71,11 @@ pub fn api_docs(api: TransformOpenApi) -> TransformOpenApi {
             description: Some("Generate embeddings".into()),
             ..Default::default()
         })
+        .tag(Tag {
+            name: "collections".into(),
+            description: Some("Store embeddings and search them by similarity".into()),
+            ..Default::default()
+        })
         .security_scheme(
             "ApiKey",
             aide::openapi::SecurityScheme::ApiKey {
@@ -85,8 +90,10 @@ pub fn api_docs(api: TransformOpenApi) -> TransformOpenApi {
                 error: "some error happened".to_string(),
                 error_details: None,
                 error_id: Uuid::nil(),
+                code: None,
                 // This is not visible.
                 status: StatusCode::NOT_FOUND,
+                retry_after: None,
             })
         })
 }
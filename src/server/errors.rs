@@ -1,21 +1,41 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use aide::operation::OperationIo;
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE, RETRY_AFTER},
+        HeaderValue, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
 use schemars::JsonSchema;
 use serde::Serialize;
 use serde_json::Value;
 use uuid::Uuid;
 
+use super::config::env_or;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
 /// A default error response for most API errors.
-#[derive(Debug, Serialize, JsonSchema)]
+#[derive(Debug, Serialize, JsonSchema, OperationIo)]
+#[aide(output)]
 pub struct AppError {
     /// An error message.
     pub error: String,
     /// A unique error ID.
     pub error_id: Uuid,
+    /// A stable, machine-readable error code.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
     #[serde(skip)]
     pub status: StatusCode,
     /// Optional Additional error details.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_details: Option<Value>,
+    /// Seconds the client should wait before retrying, sent as `Retry-After`.
+    #[serde(skip)]
+    pub retry_after: Option<u64>,
 }
 
 impl AppError {
@@ -23,8 +43,10 @@ impl AppError {
         Self {
             error: error.to_string(),
             error_id: Uuid::new_v4(),
+            code: None,
             status: StatusCode::BAD_REQUEST,
             error_details: None,
+            retry_after: None,
         }
     }
 
@@ -33,17 +55,229 @@ impl AppError {
         self
     }
 
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.code = Some(code.to_string());
+        self
+    }
+
     pub fn with_details(mut self, details: Value) -> Self {
         self.error_details = Some(details);
         self
     }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    /// A body over `FASTEMBED_MAX_BODY_BYTES`, rejected before any handler runs.
+    pub fn payload_too_large() -> Self {
+        Self::new("request body is too large")
+            .with_status(StatusCode::PAYLOAD_TOO_LARGE)
+            .with_code("payload_too_large")
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
         let status = self.status;
+        let retry_after = self.retry_after;
+        if status.is_server_error() {
+            tracing::error!(error_id = %self.error_id, %status, code = ?self.code, error = %self.error, "request failed");
+        } else {
+            tracing::warn!(error_id = %self.error_id, %status, code = ?self.code, error = %self.error, "request failed");
+        }
+        let problem = ProblemDetails::from(&self);
         let mut res = axum::Json(self).into_response();
         *res.status_mut() = status;
+        if let Some(seconds) = retry_after {
+            res.headers_mut().insert(RETRY_AFTER, seconds.into());
+        }
+        // Kept so `problem_details` can rewrite the body for clients that ask for it
+        res.extensions_mut().insert(problem);
         res
     }
 }
+
+/// An RFC 7807 problem document, with the error ID and code as extension members.
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct ProblemDetails {
+    /// `urn:fastembed:error:{code}`, or `about:blank` for errors without a code.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// The request path the error occurred on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    pub error_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_details: Option<Value>,
+}
+
+impl From<&AppError> for ProblemDetails {
+    fn from(error: &AppError) -> Self {
+        Self {
+            problem_type: error
+                .code
+                .as_ref()
+                .map_or("about:blank".to_string(), |code| {
+                    format!("urn:fastembed:error:{code}")
+                }),
+            title: error
+                .status
+                .canonical_reason()
+                .unwrap_or("Error")
+                .to_string(),
+            status: error.status.as_u16(),
+            detail: error.error.clone(),
+            instance: None,
+            error_id: error.error_id,
+            request_id: None,
+            code: error.code.clone(),
+            error_details: error.error_details.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ErrorConfig {
+    /// Send every error as `application/problem+json`, not only to clients that accept it.
+    pub problem_json: bool,
+}
+
+impl ErrorConfig {
+    pub fn from_env() -> Self {
+        Self {
+            problem_json: env_or("FASTEMBED_PROBLEM_JSON", false),
+        }
+    }
+}
+
+/// Rewrite error bodies as `application/problem+json` when configured or when the
+/// request's `Accept` header asks for it.
+///
+/// Bodies rejected by the size limit outside our extractors, e.g. raw uploads, are
+/// replaced with an [`AppError`] first so every `413` has the same shape.
+pub async fn problem_details(
+    State(config): State<ErrorConfig>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let wants_problem = config.problem_json
+        || request
+            .headers()
+            .get(ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|accept| accept.contains(PROBLEM_JSON));
+    let instance = request.uri().path().to_string();
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let mut response = next.run(request).await;
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE
+        && response.extensions().get::<ProblemDetails>().is_none()
+    {
+        response = AppError::payload_too_large().into_response();
+    }
+    if !wants_problem {
+        return response;
+    }
+    let Some(mut problem) = response.extensions().get::<ProblemDetails>().cloned() else {
+        return response;
+    };
+    problem.instance = Some(instance);
+    problem.request_id = request_id;
+    let (mut parts, _) = response.into_parts();
+    let body = serde_json::to_vec(&problem).unwrap_or_default();
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+    parts.headers.remove(axum::http::header::CONTENT_LENGTH);
+    Response::from_parts(parts, body.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Bytes, extract::DefaultBodyLimit, middleware, routing::post, Router};
+    use serde_json::Value;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::server::extractors::Json;
+
+    const LIMIT: usize = 16;
+
+    /// Serve `/json` and `/raw` behind the body limit and the problem middleware.
+    async fn serve(problem_json: bool) -> String {
+        let app = Router::new()
+            .route(
+                "/json",
+                post(|Json(value): Json<Value>| async { Json(value) }),
+            )
+            .route(
+                "/raw",
+                post(|body: Bytes| async move { body.len().to_string() }),
+            )
+            .layer(DefaultBodyLimit::max(LIMIT))
+            .layer(middleware::from_fn_with_state(
+                ErrorConfig { problem_json },
+                problem_details,
+            ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{address}")
+    }
+
+    async fn post_large(url: String) -> (StatusCode, Option<String>, Value) {
+        let response = reqwest::Client::new()
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(format!("\"{}\"", "x".repeat(LIMIT * 4)))
+            .send()
+            .await
+            .unwrap();
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        (status, content_type, response.json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_app_errors() {
+        let base = serve(false).await;
+        for path in ["/json", "/raw"] {
+            let (status, _, body) = post_large(format!("{base}{path}")).await;
+            assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{path}");
+            assert_eq!(body["code"], "payload_too_large", "{path}");
+            assert!(body["error_id"].is_string(), "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_problem_documents() {
+        let base = serve(true).await;
+        for path in ["/json", "/raw"] {
+            let (status, content_type, body) = post_large(format!("{base}{path}")).await;
+            assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{path}");
+            assert_eq!(content_type.as_deref(), Some(PROBLEM_JSON), "{path}");
+            assert_eq!(
+                body["type"], "urn:fastembed:error:payload_too_large",
+                "{path}"
+            );
+            assert_eq!(body["status"], 413, "{path}");
+            assert_eq!(body["instance"], path);
+        }
+    }
+}
@@ -1,5 +1,11 @@
+use std::{convert::Infallible, net::IpAddr, net::SocketAddr};
+
 use aide::operation::OperationIo;
-use axum::response::IntoResponse;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::IntoResponse,
+};
 use axum_jsonschema::JsonSchemaRejection;
 use axum_macros::FromRequest;
 use serde::Serialize;
@@ -28,7 +34,10 @@ where
 impl From<JsonSchemaRejection> for AppError {
     fn from(rejection: JsonSchemaRejection) -> Self {
         match rejection {
-            JsonSchemaRejection::Json(j) => Self::new(&j.to_string()),
+            JsonSchemaRejection::Json(j) if j.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                Self::payload_too_large()
+            }
+            JsonSchemaRejection::Json(j) => Self::new(&j.body_text()).with_status(j.status()),
             JsonSchemaRejection::Serde(_) => Self::new("invalid request"),
             JsonSchemaRejection::Schema(s) => {
                 Self::new("invalid request").with_details(json!({ "schema_validation": s.list()}))
@@ -36,3 +45,56 @@ impl From<JsonSchemaRejection> for AppError {
         }
     }
 }
+
+/// Header carrying the caller's API key.
+pub const API_KEY_HEADER: &str = "X-Auth-Key";
+
+/// Identifies the caller by API key and by remote IP address.
+#[derive(Clone, Debug, OperationIo)]
+#[aide(input)]
+pub struct ClientId {
+    pub api_key: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+impl ClientId {
+    /// Every identity limits are tracked under: the API key if present, and the IP address.
+    pub fn identities(&self) -> Vec<String> {
+        let mut identities = Vec::new();
+        if let Some(key) = &self.api_key {
+            identities.push(format!("key:{key}"));
+        }
+        if let Some(ip) = self.ip {
+            identities.push(format!("ip:{ip}"));
+        }
+        identities
+    }
+
+    /// The identity usage is reported under, preferring the API key over the IP address.
+    pub fn usage_key(&self) -> String {
+        self.identities()
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| "anonymous".to_string())
+    }
+}
+
+impl<S> FromRequestParts<S> for ClientId
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let api_key = parts
+            .headers
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        Ok(Self { api_key, ip })
+    }
+}
@@ -0,0 +1,390 @@
+use std::{net::SocketAddr, pin::Pin};
+
+use axum::http::StatusCode;
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+use super::{
+    config::env_or, errors::AppError, extractors::ClientId, extractors::API_KEY_HEADER,
+    limits::Fields, state::AppState,
+};
+use crate::embedding::{
+    embed_with_state, get_available_models, truncation::Truncation, ChunkingStrategy, DocumentId,
+    EmbedSettings, EmbeddingRequestUnit, EmbeddingResponse, JSONModelInfo,
+};
+
+pub mod proto {
+    tonic::include_proto!("fastembed.v1");
+}
+
+use proto::embedding_service_server::{EmbeddingService, EmbeddingServiceServer};
+
+const DEFAULT_GRPC_ADDR: &str = "127.0.0.1:50051";
+const STREAM_BUFFER: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct GrpcConfig {
+    pub addr: SocketAddr,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            addr: DEFAULT_GRPC_ADDR.parse().unwrap(),
+        }
+    }
+}
+
+impl GrpcConfig {
+    pub fn from_env() -> Self {
+        Self {
+            addr: env_or("FASTEMBED_GRPC_ADDR", Self::default().addr),
+        }
+    }
+}
+
+impl From<AppError> for Status {
+    fn from(error: AppError) -> Self {
+        let message = format!("{} ({})", error.error, error.error_id);
+        match error.status {
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+                Status::invalid_argument(message)
+            }
+            StatusCode::UNAUTHORIZED => Status::unauthenticated(message),
+            StatusCode::FORBIDDEN => Status::permission_denied(message),
+            StatusCode::NOT_FOUND => Status::not_found(message),
+            StatusCode::CONFLICT => Status::failed_precondition(message),
+            // gRPC reports oversized messages as exhausted resources too
+            StatusCode::PAYLOAD_TOO_LARGE | StatusCode::TOO_MANY_REQUESTS => {
+                Status::resource_exhausted(message)
+            }
+            StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(message),
+            _ => Status::internal(message),
+        }
+    }
+}
+
+impl From<JSONModelInfo> for proto::ModelInfo {
+    fn from(model: JSONModelInfo) -> Self {
+        Self {
+            name: model.name,
+            dimension: model.dimension,
+            description: model.description,
+            max_length: model.max_length.unwrap_or(0) as u32,
+        }
+    }
+}
+
+impl From<DocumentId> for proto::document_embeddings::Id {
+    fn from(id: DocumentId) -> Self {
+        match id {
+            DocumentId::Int(id) => Self::IntId(id),
+            id => Self::StringId(id.to_string()),
+        }
+    }
+}
+
+impl From<proto::document::Id> for DocumentId {
+    fn from(id: proto::document::Id) -> Self {
+        match id {
+            proto::document::Id::IntId(id) => Self::Int(id),
+            proto::document::Id::StringId(id) => Self::String(id),
+        }
+    }
+}
+
+impl From<proto::Truncation> for Truncation {
+    fn from(truncation: proto::Truncation) -> Self {
+        match truncation {
+            proto::Truncation::Unspecified => Self::default(),
+            proto::Truncation::Error => Self::Error,
+            proto::Truncation::TruncateEnd => Self::TruncateEnd,
+            proto::Truncation::TruncateStart => Self::TruncateStart,
+            proto::Truncation::MiddleOut => Self::MiddleOut,
+            proto::Truncation::Split => Self::Split,
+        }
+    }
+}
+
+impl From<EmbeddingResponse> for proto::EmbedResponse {
+    fn from(response: EmbeddingResponse) -> Self {
+        let number_of_documents = response.number_of_documents();
+        let total_time_ms = response.total_time().as_millis() as u64;
+        let preprocessing = response.preprocessing().to_vec();
+        let cache_hits = response.cache_hits();
+        let cache_misses = response.cache_misses();
+        let dedup_ratio = response.dedup_ratio();
+        Self {
+            embeddings: response
+                .into_embeddings()
+                .into_iter()
+                .map(|object| {
+                    let tokens = object
+                        .tokens()
+                        .iter()
+                        .map(|&tokens| tokens as u32)
+                        .collect();
+                    let truncated = object.truncated();
+                    let chunks_json = object
+                        .chunks()
+                        .iter()
+                        .map(|chunk| serde_json::Value::Object(chunk.clone()).to_string())
+                        .collect();
+                    let (id, embeddings, metadata) = object.into_parts();
+                    proto::DocumentEmbeddings {
+                        id: Some(id.into()),
+                        embeddings: embeddings
+                            .into_iter()
+                            .map(|values| proto::Vector { values })
+                            .collect(),
+                        metadata_json: metadata
+                            .map(|metadata| serde_json::Value::Object(metadata).to_string())
+                            .unwrap_or_default(),
+                        tokens,
+                        truncated,
+                        chunks_json,
+                    }
+                })
+                .collect(),
+            number_of_documents,
+            total_time_ms,
+            preprocessing,
+            cache_hits,
+            cache_misses,
+            dedup_ratio,
+        }
+    }
+}
+
+/// Parse an optional JSON field, empty meaning unset.
+fn parse_json<T: serde::de::DeserializeOwned>(
+    field: &str,
+    json: &str,
+) -> Result<Option<T>, Status> {
+    if json.is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_str(json)
+        .map(Some)
+        .map_err(|error| Status::invalid_argument(format!("{field} is invalid: {error}")))
+}
+
+/// gRPC front end over the same state and embedding path as the HTTP API.
+pub struct GrpcEmbeddingService {
+    state: AppState,
+}
+
+impl GrpcEmbeddingService {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+}
+
+fn client_id<T>(request: &Request<T>) -> ClientId {
+    ClientId {
+        api_key: request
+            .metadata()
+            .get(API_KEY_HEADER.to_lowercase().as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        ip: request.remote_addr().map(|addr| addr.ip()),
+    }
+}
+
+/// Embed one request the way the HTTP `/embed/generate` endpoint does, under the same limits.
+async fn embed_request(
+    state: &AppState,
+    client: &ClientId,
+    request: proto::EmbedRequest,
+) -> Result<proto::EmbedResponse, Status> {
+    state.rate_limiter.check_request(client)?;
+    let chunking: ChunkingStrategy =
+        parse_json("chunking_json", &request.chunking_json)?.unwrap_or_default();
+    chunking.validate().map_err(Status::invalid_argument)?;
+    let preprocessing = parse_json("preprocessing_json", &request.preprocessing_json)?;
+    let truncation = proto::Truncation::try_from(request.truncation)
+        .map_err(|_| Status::invalid_argument("truncation is not a known policy"))?;
+    let data: Vec<EmbeddingRequestUnit> = request
+        .documents
+        .into_iter()
+        .enumerate()
+        .map(|(i, document)| {
+            let id = document
+                .id
+                .ok_or_else(|| Status::invalid_argument(format!("documents[{i}] has no id")))?;
+            let metadata = parse_json(
+                &format!("documents[{i}].metadata_json"),
+                &document.metadata_json,
+            )?;
+            Ok(EmbeddingRequestUnit {
+                id: id.into(),
+                text_to_embed: document.text,
+                metadata,
+            })
+        })
+        .collect::<Result<_, Status>>()?;
+    let settings = EmbedSettings::new(Fields::Documents {
+        list: "documents",
+        text: "text",
+    })
+    .with_chunking(chunking)
+    .with_truncation(truncation.into())
+    .with_preprocessing(preprocessing);
+    let embeddings = embed_with_state(state, client, data, settings).await?;
+    Ok(embeddings.into())
+}
+
+type EmbedResponseStream = Pin<Box<dyn Stream<Item = Result<proto::EmbedResponse, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl EmbeddingService for GrpcEmbeddingService {
+    async fn embed(
+        &self,
+        request: Request<proto::EmbedRequest>,
+    ) -> Result<Response<proto::EmbedResponse>, Status> {
+        let client = client_id(&request);
+        let response = embed_request(&self.state, &client, request.into_inner()).await?;
+        Ok(Response::new(response))
+    }
+
+    type EmbedStreamStream = EmbedResponseStream;
+
+    async fn embed_stream(
+        &self,
+        request: Request<Streaming<proto::EmbedRequest>>,
+    ) -> Result<Response<Self::EmbedStreamStream>, Status> {
+        let client = client_id(&request);
+        let state = self.state.clone();
+        let mut inbound = request.into_inner();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            while let Some(message) = inbound.next().await {
+                let response = match message {
+                    Ok(request) => embed_request(&state, &client, request).await,
+                    Err(status) => Err(status),
+                };
+                if tx.send(response).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn list_models(
+        &self,
+        _request: Request<proto::ListModelsRequest>,
+    ) -> Result<Response<proto::ListModelsResponse>, Status> {
+        Ok(Response::new(proto::ListModelsResponse {
+            models: get_available_models().into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn model_info(
+        &self,
+        _request: Request<proto::ModelInfoRequest>,
+    ) -> Result<Response<proto::ModelInfo>, Status> {
+        Ok(Response::new(self.state.model_info.clone().into()))
+    }
+}
+
+/// Serve the gRPC API until `shutdown` resolves.
+pub async fn serve_grpc(
+    config: GrpcConfig,
+    state: AppState,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    tracing::info!(addr = %config.addr, "gRPC server listening");
+    // Messages are held to the same size as HTTP request bodies
+    let max_message_bytes = state.limits.max_body_bytes;
+    tonic::transport::Server::builder()
+        .add_service(
+            EmbeddingServiceServer::new(GrpcEmbeddingService::new(state))
+                .max_decoding_message_size(max_message_bytes),
+        )
+        .serve_with_shutdown(config.addr, shutdown)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_keep_their_type() {
+        let int: DocumentId = proto::document::Id::IntId(7).into();
+        assert_eq!(int, DocumentId::Int(7));
+        assert_eq!(
+            proto::document_embeddings::Id::from(int),
+            proto::document_embeddings::Id::IntId(7)
+        );
+        let string: DocumentId = proto::document::Id::StringId("7".to_string()).into();
+        assert_eq!(string, DocumentId::String("7".to_string()));
+        assert_eq!(
+            proto::document_embeddings::Id::from(string),
+            proto::document_embeddings::Id::StringId("7".to_string())
+        );
+    }
+
+    #[test]
+    fn app_errors_map_to_grpc_codes() {
+        for (status, code) in [
+            (StatusCode::BAD_REQUEST, tonic::Code::InvalidArgument),
+            (StatusCode::UNAUTHORIZED, tonic::Code::Unauthenticated),
+            (StatusCode::FORBIDDEN, tonic::Code::PermissionDenied),
+            (StatusCode::NOT_FOUND, tonic::Code::NotFound),
+            (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                tonic::Code::ResourceExhausted,
+            ),
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                tonic::Code::ResourceExhausted,
+            ),
+            (StatusCode::SERVICE_UNAVAILABLE, tonic::Code::Unavailable),
+            (StatusCode::INTERNAL_SERVER_ERROR, tonic::Code::Internal),
+        ] {
+            let error = AppError::new("failed").with_status(status);
+            assert_eq!(Status::from(error).code(), code, "{status}");
+        }
+    }
+
+    #[test]
+    fn responses_report_cache_and_dedup_like_http() {
+        let response: EmbeddingResponse = serde_json::from_value(serde_json::json!({
+            "number_of_documents": 2,
+            "total_time_ms": 12,
+            "time_per_document_ms": 6,
+            "embeddings": [],
+            "cache_hits": 3,
+            "cache_misses": 1,
+            "dedup_ratio": 0.25,
+            "preprocessing": ["nfkc"],
+        }))
+        .unwrap();
+        let response = proto::EmbedResponse::from(response);
+        assert_eq!(response.number_of_documents, 2);
+        assert_eq!(response.total_time_ms, 12);
+        assert_eq!(response.cache_hits, 3);
+        assert_eq!(response.cache_misses, 1);
+        assert_eq!(response.dedup_ratio, 0.25);
+        assert_eq!(response.preprocessing, vec!["nfkc"]);
+    }
+
+    #[test]
+    fn empty_json_fields_are_unset() {
+        let chunking: Option<ChunkingStrategy> = parse_json("chunking_json", "").unwrap();
+        assert!(chunking.is_none());
+        let invalid = parse_json::<ChunkingStrategy>("chunking_json", "{").unwrap_err();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn unspecified_truncation_matches_http() {
+        assert_eq!(
+            Truncation::from(proto::Truncation::Unspecified),
+            Truncation::default()
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use aide::axum::{routing::get_with, ApiRouter};
+use axum::{extract::State, http::StatusCode};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use super::{config::env_or, extractors::Json};
+use crate::{
+    embedding::{cache_dir, JSONModelInfo},
+    server::state::AppState,
+};
+
+const DEFAULT_MAX_QUEUE_DEPTH: i64 = 64;
+const DEFAULT_PROBE_INTERVAL_SECS: u64 = 30;
+const DEFAULT_PROBE_TIMEOUT_SECS: u64 = 5;
+const PROBE_PATHS: [&str; 3] = ["/healthz", "/readyz", "/status"];
+
+#[derive(Clone, Debug)]
+pub struct HealthConfig {
+    /// Readiness fails once more requests than this are waiting for the model.
+    pub max_queue_depth: i64,
+    /// Readiness reuses any embedding that succeeded this recently instead of probing the model.
+    pub probe_interval: Duration,
+    /// How long a readiness probe embedding may take before the model counts as unhealthy.
+    pub probe_timeout: Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            max_queue_depth: DEFAULT_MAX_QUEUE_DEPTH,
+            probe_interval: Duration::from_secs(DEFAULT_PROBE_INTERVAL_SECS),
+            probe_timeout: Duration::from_secs(DEFAULT_PROBE_TIMEOUT_SECS),
+        }
+    }
+}
+
+impl HealthConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            max_queue_depth: env_or("FASTEMBED_MAX_QUEUE_DEPTH", default.max_queue_depth),
+            probe_interval: Duration::from_secs(env_or(
+                "FASTEMBED_READY_PROBE_INTERVAL_SECS",
+                DEFAULT_PROBE_INTERVAL_SECS,
+            )),
+            probe_timeout: Duration::from_secs(env_or(
+                "FASTEMBED_READY_PROBE_TIMEOUT_SECS",
+                DEFAULT_PROBE_TIMEOUT_SECS,
+            )),
+        }
+    }
+}
+
+/// Process start time and readiness shared by the probe endpoints.
+pub struct Health {
+    config: HealthConfig,
+    started: Instant,
+    accepting: AtomicBool,
+    last_embedding: Mutex<Option<Instant>>,
+}
+
+impl Health {
+    pub fn new(config: HealthConfig) -> Self {
+        Self {
+            config,
+            started: Instant::now(),
+            accepting: AtomicBool::new(true),
+            last_embedding: Mutex::new(None),
+        }
+    }
+
+    /// Mark the server as no longer accepting work, failing readiness.
+    pub fn stop_accepting(&self) {
+        self.accepting.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::SeqCst)
+    }
+
+    /// Whether more requests are waiting for the model than readiness allows.
+    pub fn is_overloaded(&self, queue_depth: i64) -> bool {
+        queue_depth > self.config.max_queue_depth
+    }
+
+    /// Record that the model just produced embeddings.
+    pub fn record_embedding(&self) {
+        *self
+            .last_embedding
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+    }
+
+    fn embedded_recently(&self) -> bool {
+        self.last_embedding
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some_and(|at| at.elapsed() < self.config.probe_interval)
+    }
+}
+
+/// Whether the path is a probe endpoint, which is exempt from rate limiting.
+pub fn is_probe(path: &str) -> bool {
+    PROBE_PATHS.iter().any(|probe| path.ends_with(probe))
+}
+
+pub fn health_routes(state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .api_route(
+            "/healthz",
+            get_with(healthz, |op| op.description("Liveness probe.")),
+        )
+        .api_route(
+            "/readyz",
+            get_with(readyz, |op| {
+                op.description("Readiness probe, fails until the model can serve embeddings.")
+            }),
+        )
+        .api_route(
+            "/status",
+            get_with(status, |op| op.description("Model and process status.")),
+        )
+        .with_state(state)
+}
+
+pub async fn healthz() -> (StatusCode, Json<String>) {
+    (StatusCode::OK, Json("ok".to_string()))
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub accepting: bool,
+    pub probe_embedding: bool,
+    pub queue_depth: i64,
+    pub max_queue_depth: i64,
+}
+
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<ReadinessReport>) {
+    let accepting = state.health.is_accepting();
+    let queue_depth = state.metrics.queue_depth();
+    let max_queue_depth = state.health.config.max_queue_depth;
+    let probe_embedding = probe_embedding(&state).await;
+    let ready = accepting && probe_embedding && queue_depth <= max_queue_depth;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(ReadinessReport {
+            ready,
+            accepting,
+            probe_embedding,
+            queue_depth,
+            max_queue_depth,
+        }),
+    )
+}
+
+/// Whether the model can embed, reusing recent successes so probes rarely run inference.
+async fn probe_embedding(state: &AppState) -> bool {
+    if state.health.embedded_recently() {
+        return true;
+    }
+    // The model is busy serving a request, so it is loaded and working.
+    let Ok(permit) = state.embedding_permits.clone().try_acquire_owned() else {
+        return true;
+    };
+    let model = state.text_embedding.clone();
+    let probe = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        model
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .embed(vec!["ready"], None)
+            .is_ok()
+    });
+    let healthy = matches!(
+        tokio::time::timeout(state.health.config.probe_timeout, probe).await,
+        Ok(Ok(true))
+    );
+    if healthy {
+        state.health.record_embedding();
+    }
+    healthy
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct StatusReport {
+    pub model: JSONModelInfo,
+    pub version: String,
+    pub uptime_seconds: u64,
+    pub cache_dir: PathBuf,
+    /// Resident memory of the process, where the platform reports it.
+    pub memory_bytes: Option<u64>,
+    pub queue_depth: i64,
+}
+
+pub async fn status(State(state): State<AppState>) -> (StatusCode, Json<StatusReport>) {
+    (
+        StatusCode::OK,
+        Json(StatusReport {
+            model: state.model_info.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_seconds: state.health.started.elapsed().as_secs(),
+            cache_dir: cache_dir(),
+            memory_bytes: resident_memory_bytes(),
+            queue_depth: state.metrics.queue_depth(),
+        }),
+    )
+}
+
+/// Resident set size read from `/proc/self/status`, only available on Linux.
+fn resident_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
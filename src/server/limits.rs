@@ -0,0 +1,351 @@
+use axum::http::StatusCode;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::json;
+
+use super::{config::env_or, errors::AppError};
+use crate::embedding::EmbeddingRequestUnit;
+
+const DEFAULT_MAX_DOCUMENTS: usize = 1024;
+const DEFAULT_MAX_CHARS_PER_DOCUMENT: usize = 100_000;
+const DEFAULT_MAX_TOKENS_PER_DOCUMENT: usize = 8192;
+const DEFAULT_MAX_TOTAL_TOKENS: usize = 262_144;
+const DEFAULT_MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+const DEFAULT_MAX_TOP_K: usize = 1000;
+
+#[derive(Clone, Debug)]
+pub struct LimitsConfig {
+    /// Maximum number of documents in one request.
+    pub max_documents: usize,
+    /// Maximum characters of a single document.
+    pub max_chars_per_document: usize,
+    /// Maximum tokens of a single document.
+    pub max_tokens_per_document: usize,
+    /// Maximum tokens across every document in one request.
+    pub max_total_tokens: usize,
+    /// Maximum size of a request body, larger bodies are rejected with `413`.
+    pub max_body_bytes: usize,
+    /// Maximum results of one collection search.
+    pub max_top_k: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_documents: DEFAULT_MAX_DOCUMENTS,
+            max_chars_per_document: DEFAULT_MAX_CHARS_PER_DOCUMENT,
+            max_tokens_per_document: DEFAULT_MAX_TOKENS_PER_DOCUMENT,
+            max_total_tokens: DEFAULT_MAX_TOTAL_TOKENS,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            max_top_k: DEFAULT_MAX_TOP_K,
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            max_documents: env_or("FASTEMBED_MAX_DOCUMENTS", default.max_documents),
+            max_chars_per_document: env_or(
+                "FASTEMBED_MAX_CHARS_PER_DOCUMENT",
+                default.max_chars_per_document,
+            ),
+            max_tokens_per_document: env_or(
+                "FASTEMBED_MAX_TOKENS_PER_DOCUMENT",
+                default.max_tokens_per_document,
+            ),
+            max_total_tokens: env_or("FASTEMBED_MAX_TOTAL_TOKENS", default.max_total_tokens),
+            max_body_bytes: env_or("FASTEMBED_MAX_BODY_BYTES", default.max_body_bytes),
+            max_top_k: env_or("FASTEMBED_MAX_TOP_K", default.max_top_k),
+        }
+    }
+
+    /// Check the number of search results asked for.
+    pub fn check_top_k(&self, top_k: usize) -> Result<(), AppError> {
+        let mut violations = Vec::new();
+        if top_k == 0 {
+            violations.push(Violation::new("top_k", "at least one result is required"));
+        }
+        if top_k > self.max_top_k {
+            violations.push(Violation::new(
+                "top_k",
+                format!("{top_k} results exceed the limit of {}", self.max_top_k),
+            ));
+        }
+        Violation::check(violations)
+    }
+
+    /// Check the document count and the length of every document, before any tokenizing.
+    pub fn check_documents(
+        &self,
+        fields: &Fields,
+        data: &[EmbeddingRequestUnit],
+    ) -> Result<(), AppError> {
+        let mut violations = Vec::new();
+        if data.is_empty() {
+            violations.extend(fields.on_lists("at least one document is required"));
+        }
+        if data.len() > self.max_documents {
+            violations.extend(fields.on_lists(format!(
+                "{} documents exceed the limit of {}",
+                data.len(),
+                self.max_documents
+            )));
+        }
+        for (i, unit) in data.iter().enumerate() {
+            let chars = unit.text_to_embed.chars().count();
+            if chars > self.max_chars_per_document {
+                violations.push(Violation::new(
+                    fields.element(i),
+                    format!(
+                        "{chars} characters exceed the limit of {}",
+                        self.max_chars_per_document
+                    ),
+                ));
+            }
+        }
+        Violation::check(violations)
+    }
+
+    /// Check the token count of every document and of the whole request.
+    pub fn check_tokens(&self, fields: &Fields, tokens: &[usize]) -> Result<(), AppError> {
+        let mut violations: Vec<Violation> = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, &tokens)| tokens > self.max_tokens_per_document)
+            .map(|(i, tokens)| {
+                Violation::new(
+                    fields.element(i),
+                    format!(
+                        "{tokens} tokens exceed the limit of {}",
+                        self.max_tokens_per_document
+                    ),
+                )
+            })
+            .collect();
+        let total: usize = tokens.iter().sum();
+        if total > self.max_total_tokens {
+            violations.extend(fields.on_lists(format!(
+                "{total} tokens in total exceed the limit of {}",
+                self.max_total_tokens
+            )));
+        }
+        Violation::check(violations)
+    }
+}
+
+/// The request fields embedded texts were taken from, naming them in violations.
+#[derive(Clone, Copy, Debug)]
+pub enum Fields {
+    /// Documents of a list, each text in a field of the document, e.g. `data[3].text_to_embed`.
+    Documents {
+        list: &'static str,
+        text: &'static str,
+    },
+    /// Two lists of strings embedded back to back, e.g. `sources[3]` then `targets[0]`.
+    Pair {
+        first: &'static str,
+        first_len: usize,
+        second: &'static str,
+    },
+    /// One field every text was taken from, e.g. `query` or an uploaded `body`.
+    Single(&'static str),
+}
+
+impl Fields {
+    /// `data[i].text_to_embed`, the documents of `/embed/generate` and most other endpoints.
+    pub const DATA: Self = Self::Documents {
+        list: "data",
+        text: "text_to_embed",
+    };
+
+    /// Path of the field the `i`th text came from.
+    fn element(&self, i: usize) -> String {
+        match *self {
+            Self::Documents { list, text } => format!("{list}[{i}].{text}"),
+            Self::Pair {
+                first, first_len, ..
+            } if i < first_len => format!("{first}[{i}]"),
+            Self::Pair {
+                first_len, second, ..
+            } => format!("{second}[{}]", i - first_len),
+            Self::Single(field) => field.to_string(),
+        }
+    }
+
+    /// The same violation on every list the texts came from.
+    fn on_lists(&self, message: impl Into<String>) -> Vec<Violation> {
+        let message = message.into();
+        let lists = match *self {
+            Self::Documents { list, .. } => vec![list],
+            Self::Pair { first, second, .. } => vec![first, second],
+            Self::Single(field) => vec![field],
+        };
+        lists
+            .into_iter()
+            .map(|list| Violation::new(list, message.clone()))
+            .collect()
+    }
+}
+
+/// A request field that breaks a limit.
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct Violation {
+    /// Path of the offending field, e.g. `data[3].text_to_embed`.
+    pub field: String,
+    pub message: String,
+}
+
+impl Violation {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+
+    fn check(violations: Vec<Self>) -> Result<(), AppError> {
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(AppError::new("request exceeds limits")
+            .with_status(StatusCode::UNPROCESSABLE_ENTITY)
+            .with_details(json!({ "violations": violations })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedding::DocumentId;
+
+    fn documents(texts: &[&str]) -> Vec<EmbeddingRequestUnit> {
+        texts
+            .iter()
+            .enumerate()
+            .map(|(i, text)| EmbeddingRequestUnit {
+                id: DocumentId::Int(i as i64),
+                text_to_embed: text.to_string(),
+                metadata: None,
+            })
+            .collect()
+    }
+
+    fn limits() -> LimitsConfig {
+        LimitsConfig {
+            max_documents: 2,
+            max_chars_per_document: 5,
+            max_tokens_per_document: 10,
+            max_total_tokens: 15,
+            ..LimitsConfig::default()
+        }
+    }
+
+    /// The `(field, message)` of every violation in a limits error.
+    fn violations(result: Result<(), AppError>) -> Vec<(String, String)> {
+        let error = result.unwrap_err();
+        assert_eq!(error.status, StatusCode::UNPROCESSABLE_ENTITY);
+        error.error_details.unwrap()["violations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|violation| {
+                (
+                    violation["field"].as_str().unwrap().to_string(),
+                    violation["message"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn fields(violations: Vec<(String, String)>) -> Vec<String> {
+        violations.into_iter().map(|(field, _)| field).collect()
+    }
+
+    #[test]
+    fn documents_within_limits_pass() {
+        let limits = limits();
+        assert!(limits
+            .check_documents(&Fields::DATA, &documents(&["short", "ok"]))
+            .is_ok());
+        assert!(limits.check_tokens(&Fields::DATA, &[10, 5]).is_ok());
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        let result = limits().check_documents(&Fields::DATA, &documents(&["ok", "too long", "ok"]));
+        assert_eq!(
+            violations(result),
+            vec![
+                (
+                    "data".to_string(),
+                    "3 documents exceed the limit of 2".to_string()
+                ),
+                (
+                    "data[1].text_to_embed".to_string(),
+                    "8 characters exceed the limit of 5".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn characters_are_counted_not_bytes() {
+        assert!(limits()
+            .check_documents(&Fields::DATA, &documents(&["héllo"]))
+            .is_ok());
+    }
+
+    #[test]
+    fn empty_requests_are_rejected() {
+        let result = limits().check_documents(&Fields::DATA, &[]);
+        assert_eq!(fields(violations(result)), vec!["data"]);
+    }
+
+    #[test]
+    fn token_violations_name_the_document_and_the_request() {
+        let result = limits().check_tokens(&Fields::DATA, &[5, 11]);
+        assert_eq!(
+            fields(violations(result)),
+            vec!["data[1].text_to_embed", "data"]
+        );
+    }
+
+    #[test]
+    fn pairs_name_the_list_each_text_came_from() {
+        let pair = Fields::Pair {
+            first: "sources",
+            first_len: 2,
+            second: "targets",
+        };
+        let result = limits().check_tokens(&pair, &[11, 1, 1, 11]);
+        assert_eq!(
+            fields(violations(result)),
+            vec!["sources[0]", "targets[1]", "sources", "targets"]
+        );
+    }
+
+    #[test]
+    fn single_fields_are_named_as_is() {
+        let result = limits().check_documents(&Fields::Single("query"), &documents(&["too long"]));
+        assert_eq!(fields(violations(result)), vec!["query"]);
+        let grpc = Fields::Documents {
+            list: "documents",
+            text: "text",
+        };
+        let result = limits().check_tokens(&grpc, &[11]);
+        assert_eq!(fields(violations(result)), vec!["documents[0].text"]);
+    }
+
+    #[test]
+    fn top_k_must_be_positive_and_bounded() {
+        let limits = LimitsConfig {
+            max_top_k: 10,
+            ..LimitsConfig::default()
+        };
+        assert!(limits.check_top_k(10).is_ok());
+        assert_eq!(fields(violations(limits.check_top_k(0))), vec!["top_k"]);
+        assert_eq!(fields(violations(limits.check_top_k(11))), vec!["top_k"]);
+    }
+}
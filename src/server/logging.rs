@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::{extract::Request, http::HeaderValue};
+use tracing::Span;
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use uuid::Uuid;
+
+use super::config::{env_opt, env_or};
+
+const DEFAULT_LOG_FILTER: &str = "fastembed_axum=info,tower_http=info";
+
+static LOG_RAW_TEXT: AtomicBool = AtomicBool::new(false);
+
+/// Logging options, the filter itself is read from `RUST_LOG`.
+#[derive(Clone, Debug, Default)]
+pub struct LoggingConfig {
+    /// Emit one JSON object per line instead of human readable output.
+    pub json: bool,
+    /// Include the raw text of documents in debug logs.
+    pub log_raw_text: bool,
+    /// OTLP collector to export spans to, requires the `otel` feature.
+    pub otlp_endpoint: Option<String>,
+}
+
+impl LoggingConfig {
+    pub fn from_env() -> Self {
+        Self {
+            json: env_or("FASTEMBED_LOG_FORMAT", String::new()) == "json",
+            log_raw_text: env_or("FASTEMBED_LOG_RAW_TEXT", false),
+            otlp_endpoint: env_opt("OTEL_EXPORTER_OTLP_ENDPOINT"),
+        }
+    }
+}
+
+/// Install the global tracing subscriber. Calling it more than once is a no-op.
+pub fn init_tracing(config: &LoggingConfig) {
+    LOG_RAW_TEXT.store(config.log_raw_text, Ordering::Relaxed);
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    #[cfg(feature = "otel")]
+    let (otel, otel_error) = match config
+        .otlp_endpoint
+        .as_deref()
+        .map(super::telemetry::otel_layer)
+    {
+        Some(Ok(layer)) => (Some(layer), None),
+        Some(Err(error)) => (None, Some(error)),
+        None => (None, None),
+    };
+    #[cfg(not(feature = "otel"))]
+    let otel: Option<tracing_subscriber::layer::Identity> = None;
+    let registry = tracing_subscriber::registry().with(filter).with(otel);
+    let _ = if config.json {
+        registry
+            .with(fmt::layer().json().with_current_span(true))
+            .try_init()
+    } else {
+        registry.with(fmt::layer()).try_init()
+    };
+    #[cfg(feature = "otel")]
+    if let Some(error) = otel_error {
+        tracing::error!(%error, "failed to create OTLP exporter, spans will not be exported");
+    }
+}
+
+/// Whether raw document text may be written to the logs.
+pub fn log_raw_text() -> bool {
+    LOG_RAW_TEXT.load(Ordering::Relaxed)
+}
+
+/// Span wrapping every HTTP request, tagged with a request id.
+pub fn make_request_span(request: &Request) -> Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value: &HeaderValue| value.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %request.method(),
+        uri = %request.uri().path(),
+    );
+    #[cfg(feature = "otel")]
+    super::telemetry::set_remote_parent(&span, request.headers());
+    span
+}
@@ -0,0 +1,264 @@
+use std::time::{Duration, Instant};
+
+use aide::axum::ApiRouter;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header::CONTENT_TYPE, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use prometheus::{
+    Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, Opts, Registry, TextEncoder,
+};
+
+use crate::server::state::AppState;
+
+/// Prometheus metrics for the HTTP API and the embedding model.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    embedding_duration: Histogram,
+    embedded_documents: IntCounter,
+    embedded_chunks: IntCounter,
+    embedded_tokens: IntCounter,
+    cache_requests: IntCounterVec,
+    embedding_throughput: GaugeVec,
+    batch_size: Histogram,
+    queue_depth: IntGauge,
+    model_load_time: Gauge,
+    active_model: GaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("fastembed".to_string()), None)
+            .expect("Invalid metrics prefix");
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["route", "method", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route",
+            ),
+            &["route", "method"],
+        )
+        .unwrap();
+        let embedding_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "embedding_duration_seconds",
+                "Time spent embedding a request",
+            )
+            .buckets(vec![
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+            ]),
+        )
+        .unwrap();
+        let embedded_documents =
+            IntCounter::new("embedded_documents_total", "Documents embedded").unwrap();
+        let embedded_chunks = IntCounter::new("embedded_chunks_total", "Chunks embedded").unwrap();
+        let embedded_tokens = IntCounter::new("embedded_tokens_total", "Tokens embedded").unwrap();
+        let cache_requests = IntCounterVec::new(
+            Opts::new(
+                "embedding_cache_requests_total",
+                "Chunk lookups in the embedding cache by result",
+            ),
+            &["result"],
+        )
+        .unwrap();
+        let embedding_throughput = GaugeVec::new(
+            Opts::new(
+                "embedding_throughput_per_second",
+                "Documents, chunks or tokens per second of the last embedding request",
+            ),
+            &["unit"],
+        )
+        .unwrap();
+        let batch_size = Histogram::with_opts(
+            HistogramOpts::new(
+                "embedding_batch_size",
+                "Chunks sent to the model per request",
+            )
+            .buckets(prometheus::exponential_buckets(1.0, 2.0, 14).unwrap()),
+        )
+        .unwrap();
+        let queue_depth = IntGauge::new(
+            "embedding_queue_depth",
+            "Requests waiting for the embedding model",
+        )
+        .unwrap();
+        let model_load_time = Gauge::new(
+            "model_load_time_seconds",
+            "Time taken to load the active model",
+        )
+        .unwrap();
+        let active_model = GaugeVec::new(
+            Opts::new("active_model", "The model currently serving embeddings"),
+            &["model"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(embedding_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(embedded_documents.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(embedded_chunks.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(embedded_tokens.clone()))
+            .unwrap();
+        registry.register(Box::new(cache_requests.clone())).unwrap();
+        registry
+            .register(Box::new(embedding_throughput.clone()))
+            .unwrap();
+        registry.register(Box::new(batch_size.clone())).unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        registry
+            .register(Box::new(model_load_time.clone()))
+            .unwrap();
+        registry.register(Box::new(active_model.clone())).unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            embedding_duration,
+            embedded_documents,
+            embedded_chunks,
+            embedded_tokens,
+            cache_requests,
+            embedding_throughput,
+            batch_size,
+            queue_depth,
+            model_load_time,
+            active_model,
+        }
+    }
+
+    pub fn observe_model_load(&self, model_name: &str, duration: Duration) {
+        self.model_load_time.set(duration.as_secs_f64());
+        self.active_model.reset();
+        self.active_model.with_label_values(&[model_name]).set(1.0);
+    }
+
+    pub fn observe_embedding(
+        &self,
+        documents: usize,
+        chunks: usize,
+        tokens: usize,
+        duration: Duration,
+    ) {
+        let seconds = duration.as_secs_f64();
+        self.embedding_duration.observe(seconds);
+        self.batch_size.observe(chunks as f64);
+        self.embedded_documents.inc_by(documents as u64);
+        self.embedded_chunks.inc_by(chunks as u64);
+        self.embedded_tokens.inc_by(tokens as u64);
+        if seconds > 0.0 {
+            for (unit, count) in [
+                ("documents", documents),
+                ("chunks", chunks),
+                ("tokens", tokens),
+            ] {
+                self.embedding_throughput
+                    .with_label_values(&[unit])
+                    .set(count as f64 / seconds);
+            }
+        }
+    }
+
+    pub fn observe_cache(&self, hits: u32, misses: u32) {
+        self.cache_requests
+            .with_label_values(&["hit"])
+            .inc_by(hits as u64);
+        self.cache_requests
+            .with_label_values(&["miss"])
+            .inc_by(misses as u64);
+    }
+
+    /// Track a request waiting for the model until the returned guard is dropped.
+    pub fn enqueue(&self) -> QueueGuard {
+        self.queue_depth.inc();
+        QueueGuard(self.queue_depth.clone())
+    }
+
+    pub fn queue_depth(&self) -> i64 {
+        self.queue_depth.get()
+    }
+
+    fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decrements the queue depth when dropped.
+pub struct QueueGuard(IntGauge);
+
+impl Drop for QueueGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Middleware counting requests and their latency by matched route and status.
+pub async fn track_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    state
+        .metrics
+        .http_request_duration
+        .with_label_values(&[&route, &method])
+        .observe(start.elapsed().as_secs_f64());
+    state
+        .metrics
+        .http_requests
+        .with_label_values(&[&route, &method, response.status().as_str()])
+        .inc();
+    response
+}
+
+pub fn metrics_routes(state: AppState) -> ApiRouter {
+    ApiRouter::new().route("/", get(metrics)).with_state(state)
+}
+
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    match state.metrics.render() {
+        Ok(body) => (
+            StatusCode::OK,
+            [(CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+            body,
+        )
+            .into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
@@ -1,5 +1,16 @@
+pub mod config;
 pub mod docs;
 pub mod errors;
 pub mod extractors;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod health;
+pub mod limits;
+pub mod logging;
+pub mod metrics;
+pub mod rate_limit;
 pub mod run;
+pub mod shutdown;
 pub mod state;
+#[cfg(feature = "otel")]
+pub mod telemetry;
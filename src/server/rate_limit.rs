@@ -0,0 +1,643 @@
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use aide::axum::{routing::get_with, ApiRouter};
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use lru::LruCache;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    config::{env_opt, env_or},
+    errors::AppError,
+    extractors::ClientId,
+    extractors::Json,
+    health::is_probe,
+};
+use crate::server::state::AppState;
+
+const DEFAULT_USAGE_FILE: &str = "./.fastembed_usage.json";
+const DEFAULT_MAX_CLIENTS: usize = 10_000;
+const DEFAULT_USAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Limits applied to every client. `None` disables the corresponding limit.
+#[derive(Clone, Debug)]
+pub struct RateLimitConfig {
+    pub requests_per_minute: Option<u64>,
+    pub documents_per_minute: Option<u64>,
+    pub tokens_per_minute: Option<u64>,
+    pub daily_request_quota: Option<u64>,
+    pub daily_document_quota: Option<u64>,
+    pub daily_token_quota: Option<u64>,
+    pub usage_file: PathBuf,
+    /// Known API keys. When empty keys are not trusted, so limits are tracked per IP address.
+    pub api_keys: HashSet<String>,
+    /// API key allowed to see the usage of every client, others only see their own.
+    pub admin_key: Option<String>,
+    /// Clients whose buckets are kept, the least recently seen are evicted beyond this.
+    pub max_clients: usize,
+    /// How often usage is written to `usage_file`.
+    pub usage_flush_interval: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: None,
+            documents_per_minute: None,
+            tokens_per_minute: None,
+            daily_request_quota: None,
+            daily_document_quota: None,
+            daily_token_quota: None,
+            usage_file: PathBuf::from(DEFAULT_USAGE_FILE),
+            api_keys: HashSet::new(),
+            admin_key: None,
+            max_clients: DEFAULT_MAX_CLIENTS,
+            usage_flush_interval: DEFAULT_USAGE_FLUSH_INTERVAL,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            requests_per_minute: env_opt("FASTEMBED_RATE_LIMIT_REQUESTS_PER_MINUTE"),
+            documents_per_minute: env_opt("FASTEMBED_RATE_LIMIT_DOCUMENTS_PER_MINUTE"),
+            tokens_per_minute: env_opt("FASTEMBED_RATE_LIMIT_TOKENS_PER_MINUTE"),
+            daily_request_quota: env_opt("FASTEMBED_QUOTA_REQUESTS_PER_DAY"),
+            daily_document_quota: env_opt("FASTEMBED_QUOTA_DOCUMENTS_PER_DAY"),
+            daily_token_quota: env_opt("FASTEMBED_QUOTA_TOKENS_PER_DAY"),
+            usage_file: env_opt("FASTEMBED_USAGE_FILE").unwrap_or(default.usage_file),
+            api_keys: env_opt::<String>("FASTEMBED_API_KEYS")
+                .map(|keys| {
+                    keys.split(',')
+                        .map(str::trim)
+                        .filter(|key| !key.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            admin_key: env_opt("FASTEMBED_ADMIN_KEY"),
+            max_clients: env_or("FASTEMBED_RATE_LIMIT_MAX_CLIENTS", default.max_clients),
+            usage_flush_interval: env_opt("FASTEMBED_USAGE_FLUSH_INTERVAL_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.usage_flush_interval),
+        }
+    }
+}
+
+/// A token bucket refilling continuously up to `capacity` over one minute.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    available: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn per_minute(capacity: u64) -> Self {
+        Self {
+            capacity: capacity as f64,
+            available: capacity as f64,
+            refill_per_sec: capacity as f64 / 60.0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = (now - self.last_refill).as_secs_f64();
+        self.available = (self.available + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Time to wait before `amount` can be taken, or `None` if it can be taken now.
+    /// Requests larger than the bucket are let through once it is full, leaving it in debt.
+    fn wait_time(&mut self, amount: f64) -> Option<Duration> {
+        self.refill();
+        let needed = amount.min(self.capacity);
+        if self.available >= needed {
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (needed - self.available) / self.refill_per_sec,
+            ))
+        }
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.available -= amount;
+    }
+}
+
+#[derive(Debug, Default)]
+struct ClientBuckets {
+    requests: Option<TokenBucket>,
+    documents: Option<TokenBucket>,
+    tokens: Option<TokenBucket>,
+}
+
+impl ClientBuckets {
+    fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            requests: config.requests_per_minute.map(TokenBucket::per_minute),
+            documents: config.documents_per_minute.map(TokenBucket::per_minute),
+            tokens: config.tokens_per_minute.map(TokenBucket::per_minute),
+        }
+    }
+}
+
+/// Consumption of a single client over the current day.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct Usage {
+    pub requests: u64,
+    pub documents: u64,
+    pub tokens: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct UsageStore {
+    /// Days since the unix epoch (UTC) the usage was recorded on.
+    day: u64,
+    clients: HashMap<String, Usage>,
+}
+
+/// Per client token buckets and persisted daily quotas.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<LruCache<String, ClientBuckets>>,
+    usage: Mutex<UsageStore>,
+    /// Whether usage changed since it was last written.
+    dirty: AtomicBool,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let usage = std::fs::read(&config.usage_file)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        let max_clients = NonZeroUsize::new(config.max_clients).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            config,
+            buckets: Mutex::new(LruCache::new(max_clients)),
+            usage: Mutex::new(usage),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Write usage every `usage_flush_interval` in the background, when it changed.
+    pub fn spawn_periodic_flush(self: &Arc<Self>) {
+        let limiter = Arc::clone(self);
+        tokio::spawn(async move {
+            let period = limiter
+                .config
+                .usage_flush_interval
+                .max(Duration::from_secs(1));
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let limiter = Arc::clone(&limiter);
+                if let Err(error) = tokio::task::spawn_blocking(move || limiter.flush()).await {
+                    tracing::warn!(%error, "usage flush task failed");
+                }
+            }
+        });
+    }
+
+    /// The client limits are tracked for. A key only counts once validated against
+    /// `api_keys`, otherwise a client could reset its limits by sending a fresh key.
+    fn authenticate(&self, client: &ClientId) -> Result<ClientId, AppError> {
+        match &client.api_key {
+            Some(key) if !self.config.api_keys.is_empty() => {
+                if self.config.api_keys.contains(key) {
+                    Ok(client.clone())
+                } else {
+                    Err(AppError::new("invalid API key")
+                        .with_status(StatusCode::UNAUTHORIZED)
+                        .with_code("invalid_api_key"))
+                }
+            }
+            _ => Ok(ClientId {
+                api_key: None,
+                ip: client.ip,
+            }),
+        }
+    }
+
+    pub fn is_admin(&self, client: &ClientId) -> bool {
+        matches!((&self.config.admin_key, &client.api_key), (Some(admin), Some(key)) if admin == key)
+    }
+
+    /// Check and record a single request for every identity of the client.
+    pub fn check_request(&self, client: &ClientId) -> Result<(), AppError> {
+        self.check(client, 1, 0, 0)
+    }
+
+    /// Check and record the documents and tokens of an embedding request.
+    pub fn check_embedding(
+        &self,
+        client: &ClientId,
+        documents: u64,
+        tokens: u64,
+    ) -> Result<(), AppError> {
+        self.check(client, 0, documents, tokens)
+    }
+
+    fn check(
+        &self,
+        client: &ClientId,
+        requests: u64,
+        documents: u64,
+        tokens: u64,
+    ) -> Result<(), AppError> {
+        let client = self.authenticate(client)?;
+        let quota_identity = client.usage_key();
+        let identities = client.identities();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let mut wait = Duration::ZERO;
+        for identity in &identities {
+            let client_buckets =
+                buckets.get_or_insert_mut(identity.clone(), || ClientBuckets::new(&self.config));
+            for (bucket, amount) in [
+                (&mut client_buckets.requests, requests),
+                (&mut client_buckets.documents, documents),
+                (&mut client_buckets.tokens, tokens),
+            ] {
+                if let (Some(bucket), true) = (bucket, amount > 0) {
+                    if let Some(bucket_wait) = bucket.wait_time(amount as f64) {
+                        wait = wait.max(bucket_wait);
+                    }
+                }
+            }
+        }
+        if !wait.is_zero() {
+            return Err(too_many_requests("rate limit exceeded", wait.as_secs() + 1));
+        }
+
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let today = current_day();
+        if usage.day != today {
+            usage.day = today;
+            usage.clients.clear();
+        }
+        let client_usage = usage.clients.entry(quota_identity).or_default();
+        let over_quota = [
+            (
+                self.config.daily_request_quota,
+                client_usage.requests + requests,
+            ),
+            (
+                self.config.daily_document_quota,
+                client_usage.documents + documents,
+            ),
+            (self.config.daily_token_quota, client_usage.tokens + tokens),
+        ]
+        .into_iter()
+        .any(|(quota, used)| matches!(quota, Some(quota) if used > quota));
+        if over_quota {
+            return Err(too_many_requests(
+                "daily quota exceeded",
+                seconds_until_next_day(),
+            ));
+        }
+
+        for identity in &identities {
+            if let Some(client_buckets) = buckets.get_mut(identity) {
+                for (bucket, amount) in [
+                    (&mut client_buckets.requests, requests),
+                    (&mut client_buckets.documents, documents),
+                    (&mut client_buckets.tokens, tokens),
+                ] {
+                    if let Some(bucket) = bucket {
+                        bucket.take(amount as f64);
+                    }
+                }
+            }
+        }
+        client_usage.requests += requests;
+        client_usage.documents += documents;
+        client_usage.tokens += tokens;
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Write the current usage to the usage file if it changed.
+    pub fn flush(&self) {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let bytes = {
+            let usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+            serde_json::to_vec(&*usage)
+        };
+        match bytes {
+            Ok(bytes) => {
+                if let Err(error) = std::fs::write(&self.config.usage_file, bytes) {
+                    self.dirty.store(true, Ordering::Relaxed);
+                    tracing::warn!(%error, "failed to persist usage");
+                }
+            }
+            Err(error) => tracing::warn!(%error, "failed to serialize usage"),
+        }
+    }
+
+    /// Usage of every client, or only of the client counted under `only`.
+    pub fn usage_report(&self, only: Option<&str>) -> UsageReport {
+        let usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let today = current_day();
+        let clients = if usage.day == today {
+            usage
+                .clients
+                .iter()
+                .filter(|(client, _)| only.is_none_or(|only| only == client.as_str()))
+                .map(|(client, usage)| ClientUsage {
+                    client: mask_client(client),
+                    usage: usage.clone(),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        UsageReport {
+            day: today,
+            limits: Usage {
+                requests: self.config.daily_request_quota.unwrap_or(0),
+                documents: self.config.daily_document_quota.unwrap_or(0),
+                tokens: self.config.daily_token_quota.unwrap_or(0),
+            },
+            clients,
+        }
+    }
+}
+
+fn too_many_requests(message: &str, retry_after_secs: u64) -> AppError {
+    AppError::new(message)
+        .with_status(StatusCode::TOO_MANY_REQUESTS)
+        .with_retry_after(retry_after_secs)
+}
+
+fn current_day() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / SECONDS_PER_DAY
+}
+
+fn seconds_until_next_day() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    SECONDS_PER_DAY - now % SECONDS_PER_DAY
+}
+
+/// Hide all but the first characters of an API key so the report doesn't leak them.
+fn mask_client(client: &str) -> String {
+    match client.strip_prefix("key:") {
+        Some(key) => format!("key:{}…", key.chars().take(4).collect::<String>()),
+        None => client.to_string(),
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ClientUsage {
+    /// The API key (masked) or IP address of the client.
+    pub client: String,
+    pub usage: Usage,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct UsageReport {
+    /// Days since the unix epoch (UTC) the usage covers.
+    pub day: u64,
+    /// Daily quotas, 0 means unlimited.
+    pub limits: Usage,
+    pub clients: Vec<ClientUsage>,
+}
+
+/// Middleware counting every request against the per key and per IP request limits.
+pub async fn limit_requests(
+    State(state): State<AppState>,
+    client: ClientId,
+    request: Request,
+    next: Next,
+) -> Response {
+    if is_probe(request.uri().path()) {
+        return next.run(request).await;
+    }
+    match state.rate_limiter.check_request(&client) {
+        Ok(()) => next.run(request).await,
+        Err(error) => error.into_response(),
+    }
+}
+
+pub fn usage_routes(state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .api_route(
+            "/",
+            get_with(usage, |op| {
+                op.description(
+                    "Consumption of the caller for the current day, or of every API key and IP \
+                     address with the admin API key.",
+                )
+            }),
+        )
+        .with_state(state)
+}
+
+pub async fn usage(
+    State(state): State<AppState>,
+    client: ClientId,
+) -> Result<(StatusCode, Json<UsageReport>), AppError> {
+    let limiter = &state.rate_limiter;
+    let report = if limiter.is_admin(&client) {
+        limiter.usage_report(None)
+    } else {
+        let own = limiter.authenticate(&client)?.usage_key();
+        limiter.usage_report(Some(&own))
+    };
+    Ok((StatusCode::OK, Json(report)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    fn limiter(config: RateLimitConfig) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            usage_file: std::env::temp_dir().join(format!("usage-{}.json", uuid::Uuid::new_v4())),
+            ..config
+        })
+    }
+
+    fn client(api_key: Option<&str>) -> ClientId {
+        ClientId {
+            api_key: api_key.map(str::to_string),
+            ip: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))),
+        }
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let mut bucket = TokenBucket::per_minute(60);
+        bucket.take(60.0);
+        assert!(bucket.wait_time(1.0).is_some());
+        bucket.last_refill -= Duration::from_secs(2);
+        assert!(bucket.wait_time(2.0).is_none());
+        assert!(bucket.wait_time(3.0).is_some());
+    }
+
+    #[test]
+    fn bucket_never_exceeds_capacity() {
+        let mut bucket = TokenBucket::per_minute(10);
+        bucket.last_refill -= Duration::from_secs(600);
+        bucket.refill();
+        assert_eq!(bucket.available, 10.0);
+    }
+
+    #[test]
+    fn requests_beyond_the_limit_are_rejected() {
+        let limiter = limiter(RateLimitConfig {
+            requests_per_minute: Some(2),
+            ..Default::default()
+        });
+        let client = client(None);
+        assert!(limiter.check_request(&client).is_ok());
+        assert!(limiter.check_request(&client).is_ok());
+        let error = limiter.check_request(&client).unwrap_err();
+        assert_eq!(error.status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(error.retry_after.is_some());
+    }
+
+    #[test]
+    fn quota_rolls_over_at_the_next_day() {
+        let limiter = limiter(RateLimitConfig {
+            daily_request_quota: Some(1),
+            ..Default::default()
+        });
+        let client = client(None);
+        assert!(limiter.check_request(&client).is_ok());
+        assert!(limiter.check_request(&client).is_err());
+        // Pretend the recorded usage is from yesterday
+        limiter.usage.lock().unwrap().day -= 1;
+        assert!(limiter.check_request(&client).is_ok());
+    }
+
+    #[test]
+    fn fresh_unvalidated_keys_share_the_ip_quota() {
+        let limiter = limiter(RateLimitConfig {
+            daily_request_quota: Some(1),
+            ..Default::default()
+        });
+        assert!(limiter.check_request(&client(Some("first"))).is_ok());
+        assert!(limiter.check_request(&client(Some("second"))).is_err());
+    }
+
+    #[test]
+    fn unvalidated_keys_share_the_ip_buckets() {
+        let limiter = limiter(RateLimitConfig {
+            requests_per_minute: Some(1),
+            ..Default::default()
+        });
+        assert!(limiter.check_request(&client(Some("first"))).is_ok());
+        let error = limiter.check_request(&client(Some("second"))).unwrap_err();
+        assert_eq!(error.status, StatusCode::TOO_MANY_REQUESTS);
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(
+            buckets
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .collect::<Vec<_>>(),
+            vec!["ip:10.0.0.1"]
+        );
+    }
+
+    #[test]
+    fn valid_keys_get_their_own_buckets() {
+        let limiter = limiter(RateLimitConfig {
+            requests_per_minute: Some(1),
+            api_keys: HashSet::from(["known".to_string()]),
+            ..Default::default()
+        });
+        assert!(limiter.check_request(&client(Some("known"))).is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn usage_reports_can_be_limited_to_one_client() {
+        let limiter = limiter(RateLimitConfig::default());
+        limiter.check_request(&client(None)).unwrap();
+        let other = ClientId {
+            api_key: None,
+            ip: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))),
+        };
+        limiter.check_request(&other).unwrap();
+        assert_eq!(limiter.usage_report(None).clients.len(), 2);
+        let own = limiter.usage_report(Some("ip:10.0.0.2")).clients;
+        assert_eq!(own.len(), 1);
+        assert_eq!(own[0].client, "ip:10.0.0.2");
+        assert_eq!(own[0].usage.requests, 1);
+    }
+
+    #[test]
+    fn only_the_admin_key_is_admin() {
+        let limiter = limiter(RateLimitConfig {
+            admin_key: Some("admin".to_string()),
+            ..Default::default()
+        });
+        assert!(limiter.is_admin(&client(Some("admin"))));
+        assert!(!limiter.is_admin(&client(Some("other"))));
+        assert!(!limiter.is_admin(&client(None)));
+        let without_admin = self::limiter(RateLimitConfig::default());
+        assert!(!without_admin.is_admin(&client(Some("admin"))));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected_when_keys_are_configured() {
+        let limiter = limiter(RateLimitConfig {
+            api_keys: HashSet::from(["known".to_string()]),
+            ..Default::default()
+        });
+        assert!(limiter.check_request(&client(Some("known"))).is_ok());
+        let error = limiter.check_request(&client(Some("unknown"))).unwrap_err();
+        assert_eq!(error.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn idle_clients_are_evicted() {
+        let limiter = limiter(RateLimitConfig {
+            requests_per_minute: Some(10),
+            max_clients: 2,
+            ..Default::default()
+        });
+        for i in 0..5 {
+            let client = ClientId {
+                api_key: None,
+                ip: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, i))),
+            };
+            limiter.check_request(&client).unwrap();
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 2);
+    }
+}
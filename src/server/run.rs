@@ -1,44 +1,177 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
-use crate::embedding::{self};
+use crate::analysis::routes::analyze_routes;
+use crate::collections::routes::collection_routes;
+use crate::embedding::{self, cache::cache_routes, EmbeddingError};
+use crate::ingest::routes::ingest_routes;
 use crate::server::docs::{api_docs, docs_routes};
+use crate::server::errors::{problem_details, ErrorConfig};
+use crate::server::health::health_routes;
+use crate::server::logging::{init_tracing, make_request_span, LoggingConfig};
+use crate::server::metrics::{metrics_routes, track_requests};
+use crate::server::rate_limit::{limit_requests, usage_routes};
+use crate::server::shutdown::{Shutdown, ShutdownConfig};
 
-use crate::server::state::get_app_state;
+use crate::server::state::{get_app_state, AppState};
 use aide::{axum::ApiRouter, openapi::OpenApi};
-use axum::{routing::get, Extension};
+use axum::{extract::DefaultBodyLimit, middleware, routing::get, Extension, Router};
 use listenfd::ListenFd;
 use tokio::net::TcpListener;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 
 const DEFAULT_BASE_API_URL: &str = "";
 
+/// Builds the embedding service for running standalone or mounting inside another axum app.
+pub struct AppBuilder {
+    base_api_url: String,
+    model_source: embedding::ModelSource,
+}
+
+impl AppBuilder {
+    pub fn new(model_source: embedding::ModelSource) -> Self {
+        Self {
+            base_api_url: DEFAULT_BASE_API_URL.to_string(),
+            model_source,
+        }
+    }
+
+    /// Prefix for every route, e.g. `/api/v1`.
+    pub fn base_api_url(mut self, base_api_url: &str) -> Self {
+        self.base_api_url = base_api_url.to_string();
+        self
+    }
+
+    /// Load the model and every service, on the caller's runtime.
+    pub async fn build(self) -> Result<App, EmbeddingError> {
+        let state = get_app_state(self.model_source).await?;
+        Ok(App::from_state(state, &self.base_api_url))
+    }
+}
+
+/// A loaded embedding service: its state and the routes serving it.
+pub struct App {
+    pub state: AppState,
+    base_api_url: String,
+}
+
+impl App {
+    pub fn from_state(state: AppState, base_api_url: &str) -> Self {
+        Self {
+            state,
+            base_api_url: base_api_url.to_string(),
+        }
+    }
+
+    fn route(&self, endpoint: &str) -> String {
+        base_api_route_builder(endpoint, &self.base_api_url)
+    }
+
+    /// The API routes without docs or middleware, to merge into another `ApiRouter`.
+    pub fn api_router(&self) -> ApiRouter {
+        let state = &self.state;
+        ApiRouter::new()
+            .route(&self.route("/"), get(embedding::routes::hello_world))
+            .merge(health_routes(state.clone()))
+            .nest_api_service(
+                &self.route("/embed"),
+                embedding::routes::embed_routes(state.clone()),
+            )
+            .nest_api_service(&self.route("/analyze"), analyze_routes(state.clone()))
+            .nest_api_service(&self.route("/ingest"), ingest_routes(state.clone()))
+            .nest_api_service(
+                &self.route("/collections"),
+                collection_routes(state.clone()),
+            )
+            .nest_api_service(&self.route("/admin/cache"), cache_routes(state.clone()))
+            .nest_api_service(&self.route("/usage"), usage_routes(state.clone()))
+            .nest_api_service(&self.route("/metrics"), metrics_routes(state.clone()))
+    }
+
+    /// The complete service: API routes, docs, rate limiting, metrics and request tracing.
+    pub fn router(&self) -> Router {
+        aide::generate::on_error(|error| {
+            tracing::warn!(%error, "failed to generate API documentation");
+        });
+        aide::generate::extract_schemas(true);
+
+        let mut api = OpenApi::default();
+        self.api_router()
+            .nest(
+                &self.route("/docs"),
+                docs_routes(self.state.clone(), Some(&self.route("/docs"))),
+            )
+            .finish_api_with(&mut api, api_docs)
+            .layer(Extension(Arc::new(api)))
+            .layer(DefaultBodyLimit::max(self.state.limits.max_body_bytes))
+            .layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                limit_requests,
+            ))
+            .layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                track_requests,
+            ))
+            .layer(middleware::from_fn_with_state(
+                ErrorConfig::from_env(),
+                problem_details,
+            ))
+            .layer(TraceLayer::new_for_http().make_span_with(make_request_span))
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+    }
+
+    /// Serve on `listener` until a shutdown signal, then drain and flush.
+    pub async fn serve(self, listener: TcpListener) {
+        let shutdown = Shutdown::new(ShutdownConfig::from_env());
+        #[cfg(feature = "grpc")]
+        {
+            let grpc = crate::server::grpc::serve_grpc(
+                crate::server::grpc::GrpcConfig::from_env(),
+                self.state.clone(),
+                shutdown.stopped(),
+            );
+            tokio::spawn(async move {
+                if let Err(error) = grpc.await {
+                    tracing::error!(%error, "gRPC server failed");
+                }
+            });
+        }
+        let server = axum::serve(
+            listener,
+            self.router()
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown.signal(self.state.clone()));
+        tokio::select! {
+            result = server => result.unwrap(),
+            _ = shutdown.drain_deadline() => {
+                tracing::warn!("drain timeout exceeded, dropping in-flight requests");
+            }
+        }
+
+        self.state.flush();
+        tracing::info!("shutdown complete");
+    }
+}
+
 #[tokio::main]
 pub async fn start_server(api_base_url: Option<&str>, model_source: embedding::ModelSource) {
-    aide::generate::on_error(|error| {
-        println!("{error}");
-    });
+    init_tracing(&LoggingConfig::from_env());
     let base_api_url = api_base_url.unwrap_or(DEFAULT_BASE_API_URL);
-    aide::generate::extract_schemas(true);
-
-    let mut api = OpenApi::default();
-    let state = get_app_state(model_source).await;
-    let app = ApiRouter::new()
-        .route(
-            &base_api_route_builder("/", base_api_url),
-            get(embedding::routes::hello_world),
-        )
-        .nest_api_service(
-            &base_api_route_builder("/embed", base_api_url),
-            embedding::routes::embed_routes(state.clone()),
-        )
-        .nest(
-            &base_api_route_builder("/docs", base_api_url),
-            docs_routes(
-                state.clone(),
-                Some(&base_api_route_builder("/docs", base_api_url)),
-            ),
-        )
-        .finish_api_with(&mut api, api_docs)
-        .layer(Extension(Arc::new(api)));
+    let app = match AppBuilder::new(model_source)
+        .base_api_url(base_api_url)
+        .build()
+        .await
+    {
+        Ok(app) => app,
+        Err(error) => {
+            tracing::error!(%error, code = error.code(), "failed to start");
+            std::process::exit(1);
+        }
+    };
 
     let mut listenfd = ListenFd::from_env();
     let listener = match listenfd.take_tcp_listener(0).unwrap() {
@@ -49,7 +182,7 @@ pub async fn start_server(api_base_url: Option<&str>, model_source: embedding::M
         }
         // otherwise fall back to local listening
         None => {
-            println!(
+            tracing::info!(
                 "Example docs are accessible at http://127.0.0.1:3100{}",
                 base_api_route_builder("/docs", base_api_url)
             );
@@ -57,7 +190,7 @@ pub async fn start_server(api_base_url: Option<&str>, model_source: embedding::M
         }
     };
 
-    axum::serve(listener, app).await.unwrap();
+    app.serve(listener).await;
 }
 
 fn base_api_route_builder(endpoint: &str, api_base_url: &str) -> String {
@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use super::{config::env_or, state::AppState};
+
+const DEFAULT_READINESS_DELAY_SECS: u64 = 0;
+const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Clone, Debug)]
+pub struct ShutdownConfig {
+    /// Time between failing readiness and closing the listener, so load balancers stop routing here.
+    pub readiness_delay: Duration,
+    /// Maximum time to wait for in-flight requests once the listener is closed.
+    pub drain_timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            readiness_delay: Duration::from_secs(DEFAULT_READINESS_DELAY_SECS),
+            drain_timeout: Duration::from_secs(DEFAULT_DRAIN_TIMEOUT_SECS),
+        }
+    }
+}
+
+impl ShutdownConfig {
+    pub fn from_env() -> Self {
+        Self {
+            readiness_delay: Duration::from_secs(env_or(
+                "FASTEMBED_SHUTDOWN_READINESS_DELAY_SECS",
+                DEFAULT_READINESS_DELAY_SECS,
+            )),
+            drain_timeout: Duration::from_secs(env_or(
+                "FASTEMBED_SHUTDOWN_DRAIN_TIMEOUT_SECS",
+                DEFAULT_DRAIN_TIMEOUT_SECS,
+            )),
+        }
+    }
+}
+
+/// Resolves on SIGINT or, on unix, SIGTERM.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Coordinates shutdown between the listener and the drain deadline.
+pub struct Shutdown {
+    config: ShutdownConfig,
+    started: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub fn new(config: ShutdownConfig) -> Self {
+        Self {
+            config,
+            started: watch::channel(false).0,
+        }
+    }
+
+    /// Future passed to `with_graceful_shutdown`: waits for a signal, fails readiness
+    /// and then lets the server stop accepting connections.
+    pub fn signal(&self, state: AppState) -> impl std::future::Future<Output = ()> + 'static {
+        let started = self.started.clone();
+        let readiness_delay = self.config.readiness_delay;
+        async move {
+            shutdown_signal().await;
+            tracing::info!("shutdown signal received, failing readiness");
+            state.health.stop_accepting();
+            tokio::time::sleep(readiness_delay).await;
+            tracing::info!("no longer accepting connections, draining in-flight requests");
+            let _ = started.send(true);
+        }
+    }
+
+    /// Resolves once the listener has stopped accepting connections, for servers
+    /// running alongside the HTTP one.
+    pub fn stopped(&self) -> impl std::future::Future<Output = ()> + 'static {
+        let mut started = self.started.subscribe();
+        async move {
+            if started.wait_for(|started| *started).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Resolves once the drain timeout has elapsed after shutdown started.
+    pub async fn drain_deadline(&self) {
+        let mut started = self.started.subscribe();
+        if started.wait_for(|started| *started).await.is_err() {
+            return std::future::pending().await;
+        }
+        tokio::time::sleep(self.config.drain_timeout).await;
+    }
+}
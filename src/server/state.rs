@@ -1,46 +1,155 @@
-use crate::embedding::{self, HFEmbeddingModelOrUserDefinedModel};
+use crate::analysis::LabelEmbeddingCache;
+use crate::collections::Collections;
+use crate::embedding::cache::{EmbeddingCache, EmbeddingCacheConfig};
+use crate::embedding::preprocess::PreprocessingProfiles;
+use crate::embedding::{self, EmbeddingError, HFEmbeddingModelOrUserDefinedModel};
+use crate::ingest::IngestConfig;
+use crate::server::config::env_or;
+use crate::server::health::{Health, HealthConfig};
+use crate::server::limits::LimitsConfig;
+use crate::server::metrics::Metrics;
+use crate::server::rate_limit::{RateLimitConfig, RateLimiter};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use fastembed::{EmbeddingModel, TextEmbedding};
+use tokio::sync::Semaphore;
+
+const DEFAULT_PERSIST_INTERVAL_SECS: u64 = 5;
 
 #[derive(Clone)]
 pub struct AppState {
     pub text_embedding: Arc<Mutex<TextEmbedding>>,
+    /// One permit per model instance, waited on asynchronously before inference
+    /// moves to a blocking thread.
+    pub embedding_permits: Arc<Semaphore>,
     pub model: Arc<Mutex<embedding::HFEmbeddingModelOrUserDefinedModel>>,
     pub model_info: embedding::JSONModelInfo,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub metrics: Arc<Metrics>,
+    pub health: Arc<Health>,
+    pub embedding_cache: Arc<EmbeddingCache>,
+    pub collections: Arc<Collections>,
+    pub label_cache: Arc<LabelEmbeddingCache>,
+    pub limits: LimitsConfig,
+    pub preprocessing_profiles: Arc<PreprocessingProfiles>,
+    pub ingest: IngestConfig,
+}
+
+impl AppState {
+    /// Persist anything held in memory, called once the server has stopped.
+    pub fn flush(&self) {
+        self.rate_limiter.flush();
+        self.embedding_cache.flush();
+        self.collections.flush();
+        #[cfg(feature = "otel")]
+        crate::server::telemetry::shutdown_tracing();
+    }
+
+    /// Write buffered cache entries and changed collections every `period` in the background.
+    fn spawn_periodic_persist(&self, period: Duration) {
+        let embedding_cache = Arc::clone(&self.embedding_cache);
+        let collections = Arc::clone(&self.collections);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period.max(Duration::from_secs(1)));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let embedding_cache = Arc::clone(&embedding_cache);
+                let collections = Arc::clone(&collections);
+                let persisted = tokio::task::spawn_blocking(move || {
+                    embedding_cache.flush();
+                    collections.flush();
+                });
+                if let Err(error) = persisted.await {
+                    tracing::warn!(%error, "persist task failed");
+                }
+            }
+        });
+    }
 }
 
-pub async fn get_app_state(model_source: embedding::ModelSource) -> AppState {
+pub async fn get_app_state(
+    model_source: embedding::ModelSource,
+) -> Result<AppState, EmbeddingError> {
+    let rate_limiter = Arc::new(RateLimiter::new(RateLimitConfig::from_env()));
+    rate_limiter.spawn_periodic_flush();
+    let metrics = Arc::new(Metrics::new());
+    let health = Arc::new(Health::new(HealthConfig::from_env()));
+    let embedding_cache = Arc::new(EmbeddingCache::new(EmbeddingCacheConfig::from_env()));
+    let collections = Arc::new(Collections::from_env());
+    let label_cache = Arc::new(LabelEmbeddingCache::new());
+    let limits = LimitsConfig::from_env();
+    let preprocessing_profiles = Arc::new(PreprocessingProfiles::from_env());
+    let ingest = IngestConfig::from_env();
+    let load_start = Instant::now();
     let state: AppState = match model_source {
         embedding::ModelSource::HuggingFace => {
             let hf_embedding_model = EmbeddingModel::BGEBaseENV15;
             let embedding_model = embedding::HFEmbeddingModelOrUserDefinedModel::HuggingFace(
                 hf_embedding_model.clone(),
             );
-            let model_info: embedding::JSONModelInfo =
-                embedding::get_current_model_info(&embedding_model).expect("Can't load model");
-            let text_embedding: TextEmbedding = embedding::new_text_embedding(&hf_embedding_model);
+            let mut model_info: embedding::JSONModelInfo =
+                embedding::get_current_model_info(&embedding_model)
+                    .map_err(|error| EmbeddingError::ModelNotLoaded(error.to_string()))?;
+            let text_embedding: TextEmbedding = embedding::new_text_embedding(&hf_embedding_model)?;
+            model_info.max_length = embedding::max_length(&text_embedding);
             AppState {
                 text_embedding: Arc::new(Mutex::new(text_embedding)),
+                embedding_permits: Arc::new(Semaphore::new(1)),
                 model: Arc::new(Mutex::new(embedding_model)),
                 model_info,
+                rate_limiter,
+                metrics,
+                health,
+                embedding_cache,
+                collections,
+                label_cache,
+                limits,
+                preprocessing_profiles,
+                ingest,
             }
         }
         embedding::ModelSource::Local(model) => {
-            let model_info: embedding::JSONModelInfo = embedding::get_current_model_info(
-                &HFEmbeddingModelOrUserDefinedModel::UserDefined(model.clone()),
-            )
-            .expect("Can't load model");
-            let text_embedding: TextEmbedding =
-                embedding::new_text_embedding_user_defined(*model.clone());
+            let mut text_embedding: TextEmbedding =
+                embedding::new_text_embedding_user_defined(*model.clone())?;
+            // User defined models don't describe themselves, so measure the dimension
+            let dimension = text_embedding
+                .embed(vec!["dimension"], None)
+                .map_err(|error| EmbeddingError::ModelNotLoaded(error.to_string()))?
+                .first()
+                .map_or(0, Vec::len);
+            let model_info = embedding::JSONModelInfo {
+                name: "user_defined".to_string(),
+                dimension: dimension as u32,
+                description: "User defined model".to_string(),
+                max_length: embedding::max_length(&text_embedding),
+            };
             AppState {
                 text_embedding: Arc::new(Mutex::new(text_embedding)),
+                embedding_permits: Arc::new(Semaphore::new(1)),
                 model: Arc::new(Mutex::new(HFEmbeddingModelOrUserDefinedModel::UserDefined(
                     model,
                 ))),
                 model_info,
+                rate_limiter,
+                metrics,
+                health,
+                embedding_cache,
+                collections,
+                label_cache,
+                limits,
+                preprocessing_profiles,
+                ingest,
             }
         }
     };
     state
+        .metrics
+        .observe_model_load(&state.model_info.name, load_start.elapsed());
+    state.spawn_periodic_persist(Duration::from_secs(env_or(
+        "FASTEMBED_PERSIST_INTERVAL_SECS",
+        DEFAULT_PERSIST_INTERVAL_SECS,
+    )));
+    Ok(state)
 }
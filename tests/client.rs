@@ -0,0 +1,124 @@
+//! Client retries and error parsing against a scripted local server.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use fastembed_axum::client::{BlockingClient, Client, ClientConfig, ClientError};
+use serde_json::json;
+
+/// Serve `/embed/model-info`, failing with each of `failures` in turn before
+/// answering with a model. Returns the address and a count of requests served.
+async fn scripted_server(failures: Vec<StatusCode>) -> (SocketAddr, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&calls);
+    let app = Router::new().route(
+        "/embed/model-info",
+        get(move || {
+            let call = counter.fetch_add(1, Ordering::SeqCst);
+            let response = match failures.get(call) {
+                Some(&status) => problem(status),
+                None => axum::Json(json!({
+                    "name": "scripted-model",
+                    "dimension": 3,
+                    "description": "Scripted test model",
+                }))
+                .into_response(),
+            };
+            async move { response }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (addr, calls)
+}
+
+fn problem(status: StatusCode) -> Response {
+    let body = json!({
+        "type": "urn:fastembed:error:overloaded",
+        "title": status.canonical_reason(),
+        "status": status.as_u16(),
+        "detail": "the model is busy",
+        "error_id": "2f1c6a3e-8d4b-4c55-9a0e-5b7d0f3c1a22",
+        "request_id": "test-request",
+        "code": "overloaded",
+    });
+    (
+        status,
+        [
+            (header::CONTENT_TYPE, "application/problem+json"),
+            (header::RETRY_AFTER, "0"),
+        ],
+        body.to_string(),
+    )
+        .into_response()
+}
+
+fn config(addr: SocketAddr, max_retries: u32) -> ClientConfig {
+    ClientConfig::new(&format!("http://{addr}"))
+        .with_max_retries(max_retries)
+        .with_backoff(Duration::from_millis(1), Duration::from_millis(10))
+}
+
+#[tokio::test]
+async fn retries_rate_limits_and_server_errors() {
+    let (addr, calls) = scripted_server(vec![
+        StatusCode::TOO_MANY_REQUESTS,
+        StatusCode::SERVICE_UNAVAILABLE,
+    ])
+    .await;
+    let client = Client::new(config(addr, 3)).unwrap();
+    let model = client.model_info().await.unwrap();
+    assert_eq!(model.name, "scripted-model");
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_retries() {
+    let (addr, calls) = scripted_server(vec![StatusCode::INTERNAL_SERVER_ERROR; 3]).await;
+    let client = Client::new(config(addr, 1)).unwrap();
+    let Err(ClientError::Api { status, .. }) = client.model_info().await else {
+        panic!("expected an API error");
+    };
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn client_errors_are_not_retried_and_problem_documents_are_parsed() {
+    let (addr, calls) = scripted_server(vec![StatusCode::UNPROCESSABLE_ENTITY]).await;
+    let client = Client::new(config(addr, 3)).unwrap();
+    let Err(ClientError::Api { status, error }) = client.model_info().await else {
+        panic!("expected an API error");
+    };
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(error.error, "the model is busy");
+    assert_eq!(error.code.as_deref(), Some("overloaded"));
+    assert_eq!(error.request_id.as_deref(), Some("test-request"));
+    assert!(error.error_id.is_some());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn blocking_client_retries() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let (addr, calls) = runtime.block_on(scripted_server(vec![
+        StatusCode::TOO_MANY_REQUESTS,
+        StatusCode::BAD_GATEWAY,
+    ]));
+    let client = BlockingClient::new(config(addr, 3)).unwrap();
+    assert_eq!(client.model_info().unwrap().dimension, 3);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
@@ -0,0 +1,85 @@
+//! End-to-end tests of the clients against the service on an ephemeral port.
+//! They download the default model, so run them with `cargo test -- --ignored`.
+
+use std::net::SocketAddr;
+
+use fastembed_axum::{
+    client::{BlockingClient, Client, ClientConfig, ClientError, EmbedOptions},
+    embedding::{
+        truncation::Truncation, ChunkingStrategy, DocumentId, EmbeddingRequestUnit, ModelSource,
+    },
+    server::run::AppBuilder,
+};
+use tokio::net::TcpListener;
+
+async fn serve() -> String {
+    let app = AppBuilder::new(ModelSource::HuggingFace)
+        .build()
+        .await
+        .expect("failed to load the model");
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service = app
+        .router()
+        .into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, service).await.unwrap() });
+    format!("http://{addr}")
+}
+
+fn document(id: i64, text: &str) -> EmbeddingRequestUnit {
+    EmbeddingRequestUnit {
+        id: DocumentId::Int(id),
+        text_to_embed: text.to_string(),
+        metadata: None,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "downloads the embedding model"]
+async fn clients_embed_against_the_server() {
+    let base_url = serve().await;
+    let client = Client::new(ClientConfig::new(&base_url)).unwrap();
+
+    let model = client.model_info().await.unwrap();
+    let whole = EmbedOptions::default().with_chunking(ChunkingStrategy::Whole);
+    let response = client
+        .embed_with_options(
+            vec![document(1, "The quick brown fox"), document(2, "jumps")],
+            &whole,
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.number_of_documents(), 2);
+    let embeddings = response.into_embeddings();
+    assert_eq!(embeddings[0].id(), &DocumentId::Int(1));
+    assert_eq!(embeddings[0].embeddings().len(), 1);
+    assert_eq!(
+        embeddings[0].embeddings()[0].len(),
+        model.dimension as usize
+    );
+
+    // Longer than any model accepts, rejected unless truncation is asked for
+    let long = "word ".repeat(5000);
+    let Err(ClientError::Api { error, .. }) = client
+        .embed_with_options(vec![document(3, &long)], &whole)
+        .await
+    else {
+        panic!("expected input_too_long");
+    };
+    assert_eq!(error.code.as_deref(), Some("input_too_long"));
+    let truncated = client
+        .embed_with_options(
+            vec![document(3, &long)],
+            &whole.clone().with_truncation(Truncation::TruncateEnd),
+        )
+        .await
+        .unwrap();
+    assert!(truncated.into_embeddings()[0].truncated());
+
+    let blocking = BlockingClient::new(ClientConfig::new(&base_url)).unwrap();
+    let blocking_model = tokio::task::spawn_blocking(move || blocking.model_info())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(blocking_model.name, model.name);
+}